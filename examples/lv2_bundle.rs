@@ -0,0 +1,25 @@
+//! writes an LV2 bundle around a release build of the library
+//!
+//! ```text
+//! cargo build --release -p beriq_fm_plugins --features lv2
+//! cargo run --example lv2_bundle --features lv2 -- [bundle dir] [library]
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use beriq_fm::plugin::lv2;
+
+fn main() {
+    let args : Vec<String> = env::args().collect();
+    let dir = args.get(1).map_or("target/beriq_fm.lv2", |s| s.as_str());
+    let library = Path::new(args.get(2).map_or("target/release/libberiq_fm_plugins.so", |s| s.as_str()));
+    // installed under the name of the engine, as the other plugin formats
+    let binary = library.file_name().and_then(|name| name.to_str()).expect("no library name")
+        .replace("beriq_fm_plugins", "beriq_fm");
+
+    lv2::write_bundle(dir, &binary).expect("cannot write the TTL files");
+    fs::copy(library, Path::new(dir).join(&binary)).expect("cannot copy the library");
+    println!("wrote {dir}");
+}
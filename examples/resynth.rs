@@ -0,0 +1,55 @@
+//! find a patch for a recorded note
+//!
+//! ```text
+//! cargo run --release --example resynth --features wav -- target.wav [key] [out.patch]
+//! ```
+//!
+//! The note should start right at the beginning of the file. The patch is
+//! written to `out.patch`, its rendering next to it as `out.wav`.
+
+use std::env;
+use std::process;
+
+use beriq_fm::synth::patch;
+use beriq_fm::synth::resynth::{self, Options, Resynth};
+use beriq_fm::synth::scheduler::render_notes;
+use beriq_fm::synth::voice::Voice;
+
+fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+    let Some(target) = args.first() else {
+        eprintln!("usage: resynth <target.wav> [key] [out.patch]");
+        process::exit(2);
+    };
+    let key = args.get(1).map_or(60, |key| key.parse().expect("key is a MIDI note number"));
+    let out = args.get(2).map_or("resynth.patch", String::as_str);
+
+    let (samples, rate) = resynth::load_wav(target).unwrap_or_else(|e| {
+        eprintln!("{target}: {e}");
+        process::exit(1);
+    });
+
+    let options = Options { key, ..Options::default() };
+    let resynth = Resynth::new(&samples, rate, Voice::new(), options.clone()).unwrap_or_else(|e| {
+        eprintln!("{target}: {e}");
+        process::exit(1);
+    });
+    let best = resynth.run(|generation, distance| println!("generation {generation:3}: distance {distance:.4}"));
+
+    patch::save(&best.voice, out).expect("patch written");
+    let length = samples.len() as f64 / rate as f64;
+    let rendered = render_notes(best.voice, &[(0.0, length, key, options.velocity)], length, rate);
+    let spec = hound::WavSpec {
+        channels : 1,
+        sample_rate : rate,
+        bits_per_sample : 32,
+        sample_format : hound::SampleFormat::Float,
+    };
+    let wav = std::path::Path::new(out).with_extension("wav");
+    let mut writer = hound::WavWriter::create(&wav, spec).expect("wav created");
+    for sample in rendered {
+        writer.write_sample(sample).expect("wav written");
+    }
+    writer.finalize().expect("wav written");
+    println!("wrote {out} and {}", wav.display());
+}
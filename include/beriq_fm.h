@@ -0,0 +1,99 @@
+/*
+ * beriq_fm
+ *
+ * C API of the FM synthesis engine. Link against libberiq_fm_plugins.so
+ * or libberiq_fm_plugins.a, built with
+ *
+ *     cargo build --release -p beriq_fm_plugins --features ffi
+ *
+ * An engine is a pool of voices playing one patch. It is not thread safe:
+ * call it from one thread at a time, typically the audio callback, and
+ * hand notes over to that thread yourself.
+ */
+
+#ifndef BERIQ_FM_H
+#define BERIQ_FM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* results */
+#define BERIQ_OK              0
+#define BERIQ_ERROR_NULL     -1
+#define BERIQ_ERROR_PATCH    -2
+#define BERIQ_ERROR_PARAM    -3
+
+/* voice parameter ids */
+#define BERIQ_PARAM_ALGORITHM     1
+#define BERIQ_PARAM_GLIDE_MODE    2
+#define BERIQ_PARAM_GLIDE_TIME    3
+#define BERIQ_PARAM_BEND_RANGE    4
+
+/* operator parameter fields; operators are numbered from 1 to 4 */
+#define BERIQ_OP_WAVEFORM         1
+#define BERIQ_OP_TOTAL_LEVEL      2
+#define BERIQ_OP_FEEDBACK_LEVEL   3
+#define BERIQ_OP_TUNE             4
+#define BERIQ_OP_ATTACK_RATE      5
+#define BERIQ_OP_DECAY_RATE       6
+#define BERIQ_OP_SUSTAIN_LEVEL    7
+#define BERIQ_OP_RELEASE_RATE     8
+#define BERIQ_OP_IS_SUSTAINED     9
+#define BERIQ_OP_SMOOTHING        10
+#define BERIQ_OP_OUTPUT           11
+
+/* id of `field` of operator `op`: BERIQ_PARAM_OP(2, BERIQ_OP_TUNE) */
+#define BERIQ_PARAM_OP(op, field) ((op) * 100 + (field))
+
+/* id of the depth at which operator `src` modulates a later operator `dst`:
+ * BERIQ_PARAM_MOD(1, 3). Setting BERIQ_PARAM_ALGORITHM replaces all depths
+ * and outputs with those of the preset. */
+#define BERIQ_PARAM_MOD(src, dst) ((src) * 10 + (dst))
+
+/* id of the depth at which operator `src` modulates an earlier operator
+ * `dst` with its output of the previous sample: BERIQ_PARAM_FEEDBACK(4, 1).
+ * Setting BERIQ_PARAM_ALGORITHM clears all feedback paths. */
+#define BERIQ_PARAM_FEEDBACK(src, dst) ((src) * 10 + (dst))
+
+typedef struct BeriqEngine BeriqEngine;
+
+/* a new engine rendering at `sample_rate` with the default patch */
+BeriqEngine *beriq_engine_new(uint32_t sample_rate);
+
+void beriq_engine_free(BeriqEngine *engine);
+
+/* load a patch file from memory, `len` bytes of UTF-8 text; sounding
+ * notes are cut. BERIQ_ERROR_PATCH if the text is not a valid patch. */
+int32_t beriq_engine_load_patch(BeriqEngine *engine, const uint8_t *data, size_t len);
+
+/* `value` is clamped to the range of the parameter.
+ * BERIQ_ERROR_PARAM if there is no parameter `id`. */
+int32_t beriq_engine_set_param(BeriqEngine *engine, uint32_t id, float value);
+
+int32_t beriq_engine_get_param(const BeriqEngine *engine, uint32_t id, float *value);
+
+void beriq_engine_note_on(BeriqEngine *engine, uint8_t key, uint8_t velocity);
+
+void beriq_engine_note_off(BeriqEngine *engine, uint8_t key);
+
+void beriq_engine_all_notes_off(BeriqEngine *engine);
+
+/* one complete MIDI channel message of `len` bytes */
+void beriq_engine_midi(BeriqEngine *engine, const uint8_t *data, size_t len);
+
+/* render `frames` frames of `channels` interleaved samples into `out`;
+ * every channel carries the same mono signal */
+void beriq_engine_render_f32(BeriqEngine *engine, float *out, size_t frames, uint32_t channels);
+
+/* as beriq_engine_render_f32, clipped to 16 bit */
+void beriq_engine_render_i16(BeriqEngine *engine, int16_t *out, size_t frames, uint32_t channels);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
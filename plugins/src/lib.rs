@@ -0,0 +1,7 @@
+//! beriq_fm_plugins
+//!
+//! the engine as shared and static libraries: the entry points of the
+//! plugin formats enabled by features are those of the `beriq_fm` crate,
+//! linked in whole.
+
+pub use beriq_fm::*;
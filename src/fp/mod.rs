@@ -1,7 +1,7 @@
-/// fixpoint numbers 16.16
+//! fixpoint numbers 16.16
 
-use std::ops;
-use std::convert::From;
+use core::ops;
+use core::convert::From;
 
 pub mod exp;
 pub mod sin;
@@ -59,6 +59,12 @@ impl From<f32> for FP {
     }
 }
 
+impl From<f64> for FP {
+    fn from(item : f64) -> Self {
+        FP { repr: (item * 65536.0) as i32 }
+    }
+}
+
 impl ops::Neg for FP {
     type Output = Self;
     fn neg(self) -> Self {
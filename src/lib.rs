@@ -0,0 +1,24 @@
+//! beriq_fm
+//!
+//! FM synthesis engine on 16.16 fixpoint arithmetic.
+//!
+//! Without the default `std` feature the crate is `no_std` and allocation
+//! free: the `fp` arithmetic, the generators, voices, the voice pool and
+//! MIDI parsing remain, for microcontrollers. Patch files, Scala tunings,
+//! parameter names, the control queue, the sequencer, the randomizer,
+//! patch morphing, resynthesis and the plugin formats need `std`:
+//!
+//! ```text
+//! cargo build --no-default-features --target thumbv7em-none-eabihf
+//! ```
+//!
+//! The shared and static libraries of the plugin formats are built by the
+//! `beriq_fm_plugins` crate in `plugins/`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![allow(clippy::needless_return)]
+
+pub mod fp;
+pub mod synth;
+pub mod midi;
+#[cfg(feature = "std")]
+pub mod plugin;
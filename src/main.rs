@@ -1,15 +1,8 @@
-#![allow(dead_code)]
-
-mod fp;
-mod synth;
-
 use std::time::Duration;
-use fp::*;
+use beriq_fm::{fp::*, synth, midi};
 use rodio::{OutputStream, source::Source};
 
-fn main() {
-    let note : fp::FP = fp::FP::raw(0x7_c807); // log2(110)
-
+fn demo_patch() -> synth::voice::Voice {
     let mut voice = synth::voice::Voice::new();
     
     voice.operators[0].wave_gen.waveform = synth::wave_generator::WaveForm::FullSine;
@@ -47,16 +40,58 @@ fn main() {
     voice.operators[3].env_gen.is_sustained = false;
     voice.operators[3].feedback_level = 0;
 
-    voice.algorithm = 0;
+    voice.set_algorithm(0);
 
-    voice.note_on(note);
+    voice
+}
+
+/// play from a virtual MIDI port until enter is pressed,
+/// with the patches and controller mapping of a rig file if given
+fn play_midi(port_name : &str, rig_path : Option<&str>) {
+    let rig = match rig_path.map(midi::mapping::Rig::load) {
+        None => midi::mapping::Rig { patches : vec![demo_patch()], map : midi::mapping::MidiMap::new() },
+        Some(Ok(rig)) => rig,
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+
+    let (_port, events) = match midi::input::VirtualPort::open("beriq_fm", port_name) {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let patch = rig.patches.first().copied().unwrap_or_else(demo_patch);
+    let mut player = midi::player::MidiPlayer::new(synth::voice_pool::VoicePool::new(patch), events);
+    player.programs = rig.patches;
+    player.map = rig.map;
+
+    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+    let _result = stream_handle.play_raw(player.convert_samples());
+
+    println!("listening on MIDI port '{port_name}', press enter to quit");
+    let mut line = String::new();
+    let _ = std::io::stdin().read_line(&mut line);
+}
+
+fn main() {
+    let args : Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--midi") {
+        play_midi(args.get(2).map(String::as_str).unwrap_or("in"), args.get(3).map(String::as_str));
+        return;
+    }
+
+    let (control, audio) = synth::control::controlled(synth::voice_pool::VoicePool::new(demo_patch()), 64);
 
     // Get an output stream handle to the default physical sound device
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let _result = stream_handle.play_raw(voice.convert_samples());
-    
-    //voice.note_on(note);
-    //voice.note_off();
-    std::thread::sleep(Duration::from_secs(10));
+    let _result = stream_handle.play_raw(audio.convert_samples());
 
+    let _ = control.note_on(57, 127); // A3, 220Hz
+    std::thread::sleep(Duration::from_secs(5));
+    let _ = control.note_off(57);
+    std::thread::sleep(Duration::from_secs(5));
 }
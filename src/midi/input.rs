@@ -0,0 +1,61 @@
+//! input
+//!
+//! a virtual MIDI input port (an ALSA sequencer client on Linux) that other
+//! applications connect to. Received events are timestamped and queued for
+//! the audio thread.
+
+use std::fmt;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use midir::{MidiInput, MidiInputConnection};
+use midir::os::unix::VirtualInput;
+
+use super::{MidiEvent, TimedEvent};
+
+/// events that fit in the queue; when the audio thread stalls, newer
+/// events are dropped rather than blocking the MIDI thread
+pub const QUEUE_SIZE : usize = 1024;
+
+#[derive(Debug)]
+pub enum MidiInputError {
+    Init(midir::InitError),
+    Connect(String),
+}
+
+impl fmt::Display for MidiInputError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiInputError::Init(e)    => write!(f, "cannot open MIDI input: {e}"),
+            MidiInputError::Connect(e) => write!(f, "cannot create MIDI port: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MidiInputError {}
+
+/// An open virtual port; the port closes when this is dropped
+pub struct VirtualPort {
+    _connection : MidiInputConnection<SyncSender<TimedEvent>>,
+}
+
+impl VirtualPort {
+    /// open a port named `port_name`, returns the port and the receiving
+    /// end of its event queue
+    pub fn open(client_name : &str, port_name : &str)
+        -> Result<(VirtualPort, Receiver<TimedEvent>), MidiInputError>
+    {
+        let input = MidiInput::new(client_name).map_err(MidiInputError::Init)?;
+        let (sender, receiver) = sync_channel(QUEUE_SIZE);
+
+        let connection = input.create_virtual(port_name,
+            |time_us, bytes, sender : &mut SyncSender<TimedEvent>| {
+                if let Some(event) = MidiEvent::parse(bytes) {
+                    let _ = sender.try_send(TimedEvent { time_us, event });
+                }
+            },
+            sender
+        ).map_err(|e| MidiInputError::Connect(e.to_string()))?;
+
+        Ok((VirtualPort { _connection : connection }, receiver))
+    }
+}
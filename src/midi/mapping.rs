@@ -0,0 +1,296 @@
+//! mapping
+//!
+//! MIDI controllers to patch parameters, with a learn mode, and program
+//! changes to slots of a patch bank.
+//!
+//! A rig file keeps the bank and the mapping together, so that a live setup
+//! reloads as it was saved:
+//!
+//! ```text
+//! [patch]
+//! algorithm = 0
+//! op1.total_level = 32
+//! [patch]
+//! ...
+//! [map]
+//! cc 74 = op2.total_level 0 255
+//! program 5 = 1
+//! ```
+//!
+//! The n-th `[patch]` section is bank slot n. In `[map]`, `cc <cc> =
+//! <param> <min> <max>` maps the controller range onto min..max and
+//! `program <program> = <slot>` selects a slot; unmapped programs select
+//! the slot with their own number.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::synth::param::*;
+use crate::synth::patch::{self, PatchError};
+use crate::synth::voice::Voice;
+use crate::synth::voice_pool::VoicePool;
+
+pub const NUM_PROGRAMS : usize = 128;
+
+const CC_MAX : f32 = 127.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CcMapping {
+    pub cc : u8,
+    pub param : Param,
+    /// parameter value at controller value 0
+    pub min : f32,
+    /// parameter value at controller value 127
+    pub max : f32,
+}
+
+impl CcMapping {
+    /// map the full range of `param`
+    pub fn new(cc : u8, param : Param) -> CcMapping {
+        let (min, max) = param.range();
+        CcMapping { cc, param, min, max }
+    }
+
+    pub fn value(&self, cc_value : u8) -> f32 {
+        self.min + (self.max - self.min) * cc_value as f32 / CC_MAX
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiMap {
+    pub ccs : Vec<CcMapping>,
+    programs : [usize; NUM_PROGRAMS],
+    learn : Option<Param>,
+}
+
+impl MidiMap {
+    pub fn new() -> MidiMap {
+        MidiMap {
+            ccs : Vec::new(),
+            programs : core::array::from_fn(|program| program),
+            learn : None,
+        }
+    }
+
+    /// map `cc` onto `mapping.param`, replacing what `cc` mapped before
+    pub fn map_cc(&mut self, mapping : CcMapping) {
+        self.unmap_cc(mapping.cc);
+        self.ccs.push(mapping);
+    }
+
+    pub fn unmap_cc(&mut self, cc : u8) {
+        self.ccs.retain(|m| m.cc != cc);
+    }
+
+    /// the next controller that moves gets mapped to `param`
+    pub fn learn(&mut self, param : Param) {
+        self.learn = Some(param);
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learn = None;
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learn.is_some()
+    }
+
+    pub fn map_program(&mut self, program : u8, slot : usize) {
+        self.programs[program as usize % NUM_PROGRAMS] = slot;
+    }
+
+    /// bank slot selected by `program`
+    pub fn slot(&self, program : u8) -> usize {
+        self.programs[program as usize % NUM_PROGRAMS]
+    }
+
+    /// set the parameters mapped to `cc` on all voices of `pool`;
+    /// returns false if `cc` is not mapped (and not being learned)
+    pub fn control_change(&mut self, cc : u8, value : u8, pool : &mut VoicePool) -> bool {
+        if let Some(param) = self.learn.take() {
+            self.map_cc(CcMapping::new(cc, param));
+        }
+
+        let mut mapped = false;
+        for mapping in self.ccs.iter().filter(|m| m.cc == cc) {
+            let value = mapping.value(value);
+            for voice in pool.voices_mut() {
+                mapping.param.set(voice, value);
+            }
+            mapped = true;
+        }
+        mapped
+    }
+
+    /// the `[map]` section lines
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for m in &self.ccs {
+            let _ = writeln!(text, "cc {} = {} {} {}", m.cc, m.param.name(), m.min, m.max);
+        }
+        for (program, slot) in self.programs.iter().enumerate() {
+            if *slot != program {
+                let _ = writeln!(text, "program {program} = {slot}");
+            }
+        }
+        text
+    }
+
+    /// add one `[map]` section line
+    pub fn parse_line(&mut self, line_nr : usize, line : &str) -> Result<(), PatchError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (lhs, rhs) = line.split_once('=').ok_or(PatchError::Syntax(line_nr))?;
+        let lhs : Vec<&str> = lhs.split_whitespace().collect();
+        let rhs : Vec<&str> = rhs.split_whitespace().collect();
+
+        match (lhs.as_slice(), rhs.as_slice()) {
+            (["cc", cc], [name, min, max]) => {
+                let param = Param::from_name(name)
+                    .ok_or_else(|| PatchError::UnknownParam(name.to_string()))?;
+                self.map_cc(CcMapping {
+                    cc : parse_number(cc)?,
+                    param,
+                    min : parse_number(min)?,
+                    max : parse_number(max)?,
+                });
+            }
+            (["program", program], [slot]) => {
+                let program : u8 = parse_number(program)?;
+                self.map_program(program, parse_number(slot)?);
+            }
+            _ => return Err(PatchError::Syntax(line_nr)),
+        }
+        Ok(())
+    }
+}
+
+impl Default for MidiMap {
+    fn default() -> Self {
+        MidiMap::new()
+    }
+}
+
+/// a patch bank and the MIDI mapping that plays it
+#[derive(Debug, Clone)]
+pub struct Rig {
+    pub patches : Vec<Voice>,
+    pub map : MidiMap,
+}
+
+impl Rig {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for voice in &self.patches {
+            text.push_str("[patch]\n");
+            text.push_str(&patch::to_text(voice));
+        }
+        text.push_str("[map]\n");
+        text.push_str(&self.map.to_text());
+        text
+    }
+
+    pub fn parse(text : &str) -> Result<Rig, PatchError> {
+        enum Section { None, Patch, Map }
+
+        let mut rig = Rig { patches : Vec::new(), map : MidiMap::new() };
+        let mut section = Section::None;
+        // values of each patch, set once all are read
+        let mut patches : Vec<Vec<(Param, f32)>> = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            match line.trim() {
+                "[patch]" => {
+                    patches.push(Vec::new());
+                    section = Section::Patch;
+                }
+                "[map]" => section = Section::Map,
+                _ => match section {
+                    Section::Patch => {
+                        patches.last_mut().unwrap().extend(patch::parse_line(idx + 1, line)?);
+                    }
+                    Section::Map => rig.map.parse_line(idx + 1, line)?,
+                    Section::None => {
+                        if patch::parse_line(idx + 1, line)?.is_some() {
+                            return Err(PatchError::Syntax(idx + 1));
+                        }
+                    }
+                },
+            }
+        }
+        rig.patches = patches.iter().map(|values| patch::from_values(values)).collect();
+        Ok(rig)
+    }
+
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> Result<(), PatchError> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Rig, PatchError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+fn parse_number<T : std::str::FromStr>(s : &str) -> Result<T, PatchError> {
+    s.parse::<T>().map_err(|_| PatchError::BadValue(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(pool : &VoicePool, op : usize) -> Vec<u8> {
+        pool.voices().map(|voice| voice.operators[op].total_level).collect()
+    }
+
+    #[test]
+    fn test_cc() {
+        let mut pool = VoicePool::new(Voice::new());
+        let mut map = MidiMap::new();
+        map.map_cc(CcMapping::new(74, Param::Op(1, OpParam::TotalLevel)));
+
+        assert!(map.control_change(74, 0, &mut pool));
+        assert!(levels(&pool, 1).iter().all(|level| *level == 0));
+        assert!(map.control_change(74, 127, &mut pool));
+        assert!(levels(&pool, 1).iter().all(|level| *level == 255));
+        assert!(!map.control_change(1, 127, &mut pool));
+
+        // learn maps the next controller that moves
+        map.learn(Param::Op(3, OpParam::TotalLevel));
+        assert!(map.control_change(1, 0, &mut pool));
+        assert!(!map.is_learning());
+        assert!(levels(&pool, 3).iter().all(|level| *level == 0));
+
+        // a mapping with its own range
+        map.map_cc(CcMapping { cc : 1, param : Param::Op(3, OpParam::TotalLevel), min : 100.0, max : 200.0 });
+        assert_eq!(map.ccs.len(), 2);
+        map.control_change(1, 127, &mut pool);
+        assert!(levels(&pool, 3).iter().all(|level| *level == 200));
+    }
+
+    #[test]
+    fn test_rig() {
+        let mut soft = Voice::new();
+        soft.operators[3].total_level = 64;
+        let mut rig = Rig { patches : vec![Voice::new(), soft], map : MidiMap::new() };
+        rig.map.map_cc(CcMapping { cc : 74, param : Param::Op(1, OpParam::Tune), min : -1.0, max : 1.5 });
+        rig.map.map_program(5, 1);
+
+        let text = rig.to_text();
+        assert!(text.contains("[map]\ncc 74 = op2.tune -1 1.5\nprogram 5 = 1\n"));
+
+        let copy = Rig::parse(&text).unwrap();
+        assert_eq!(copy.to_text(), text);
+        assert_eq!(copy.patches.len(), 2);
+        assert_eq!(copy.patches[1].operators[3].total_level, 64);
+        assert_eq!(copy.map.slot(5), 1);
+        assert_eq!(copy.map.slot(6), 6);
+
+        assert!(matches!(Rig::parse("algorithm = 1\n"), Err(PatchError::Syntax(1))));
+        assert!(matches!(Rig::parse("[map]\ncc 1 = op9.tune 0 1\n"), Err(PatchError::UnknownParam(_))));
+    }
+}
@@ -0,0 +1,110 @@
+//! MIDI
+//!
+//! channel voice messages as they drive the voice pool, live from a
+//! virtual MIDI port or from any other source of raw MIDI bytes.
+
+#[cfg(feature = "midir")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod player;
+#[cfg(feature = "std")]
+pub mod mapping;
+
+use crate::synth::voice_pool::VoicePool;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MidiEvent {
+    NoteOn { channel : u8, key : u8, velocity : u8 },
+    NoteOff { channel : u8, key : u8 },
+    ControlChange { channel : u8, cc : u8, value : u8 },
+    ProgramChange { channel : u8, program : u8 },
+    /// centered: -8192..8191
+    PitchBend { channel : u8, value : i32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimedEvent {
+    /// microseconds since the source started
+    pub time_us : u64,
+    pub event : MidiEvent,
+}
+
+impl MidiEvent {
+    /// parse one complete message; anything but the events above is None
+    pub fn parse(bytes : &[u8]) -> Option<MidiEvent> {
+        let status = *bytes.first()?;
+        let channel = status & 0x0F;
+        let data = |i : usize| bytes.get(i).copied().filter(|b| *b < 0x80);
+
+        match status & 0xF0 {
+            0x80 => Some(MidiEvent::NoteOff { channel, key : data(1)? }),
+            0x90 => {
+                let (key, velocity) = (data(1)?, data(2)?);
+                // note on with velocity 0 is a note off
+                if velocity == 0 {
+                    Some(MidiEvent::NoteOff { channel, key })
+                } else {
+                    Some(MidiEvent::NoteOn { channel, key, velocity })
+                }
+            }
+            0xB0 => Some(MidiEvent::ControlChange { channel, cc : data(1)?, value : data(2)? }),
+            0xC0 => Some(MidiEvent::ProgramChange { channel, program : data(1)? }),
+            0xE0 => {
+                let value = (data(1)? as i32) | ((data(2)? as i32) << 7);
+                Some(MidiEvent::PitchBend { channel, value : value - 0x2000 })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        match *self {
+            MidiEvent::NoteOn { channel, .. }
+            | MidiEvent::NoteOff { channel, .. }
+            | MidiEvent::ControlChange { channel, .. }
+            | MidiEvent::ProgramChange { channel, .. }
+            | MidiEvent::PitchBend { channel, .. } => channel,
+        }
+    }
+
+    /// play the event on `pool`; program changes are left to the caller
+    pub fn apply(&self, pool : &mut VoicePool) {
+        match *self {
+            MidiEvent::NoteOn { key, velocity, .. } => pool.note_on(key, velocity),
+            MidiEvent::NoteOff { key, .. } => pool.note_off(key),
+            MidiEvent::ControlChange { cc, value, .. } => pool.control_change(cc, value),
+            MidiEvent::PitchBend { value, .. } => pool.set_bend(value),
+            MidiEvent::ProgramChange { .. } => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(MidiEvent::parse(&[0x91, 60, 100]),
+            Some(MidiEvent::NoteOn { channel : 1, key : 60, velocity : 100 }));
+        assert_eq!(MidiEvent::parse(&[0x90, 60, 0]),
+            Some(MidiEvent::NoteOff { channel : 0, key : 60 }));
+        assert_eq!(MidiEvent::parse(&[0x8F, 60, 64]),
+            Some(MidiEvent::NoteOff { channel : 15, key : 60 }));
+        assert_eq!(MidiEvent::parse(&[0xB0, 64, 127]),
+            Some(MidiEvent::ControlChange { channel : 0, cc : 64, value : 127 }));
+        assert_eq!(MidiEvent::parse(&[0xC2, 5]),
+            Some(MidiEvent::ProgramChange { channel : 2, program : 5 }));
+        assert_eq!(MidiEvent::parse(&[0xE0, 0x00, 0x40]),
+            Some(MidiEvent::PitchBend { channel : 0, value : 0 }));
+        assert_eq!(MidiEvent::parse(&[0xE0, 0x7F, 0x7F]),
+            Some(MidiEvent::PitchBend { channel : 0, value : 8191 }));
+        assert_eq!(MidiEvent::parse(&[0xE0, 0x00, 0x00]),
+            Some(MidiEvent::PitchBend { channel : 0, value : -8192 }));
+
+        assert_eq!(MidiEvent::parse(&[0x90, 60]), None);
+        assert_eq!(MidiEvent::parse(&[0x90, 0x80, 1]), None);
+        assert_eq!(MidiEvent::parse(&[0xF8]), None);
+        assert_eq!(MidiEvent::parse(&[]), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! player
+//!
+//! plays timestamped MIDI events on a voice pool, as a rodio source.
+//!
+//! Event timestamps are mapped onto the sample clock: the first event is
+//! anchored `latency` samples ahead of the audio, every later event lands
+//! at its exact sample relative to it. Events that would land in the past,
+//! or too far ahead because the clocks drifted, re-anchor the mapping.
+#[cfg(feature = "rodio")]
+use std::time::Duration;
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "rodio")]
+use rodio::source::Source;
+
+use crate::synth::voice::Voice;
+use crate::synth::voice_pool::VoicePool;
+use crate::synth::SAMPLE_FREQ;
+
+use super::{MidiEvent, TimedEvent};
+use super::mapping::MidiMap;
+
+/// the event queue is checked once per block
+pub const BLOCK_SIZE : u64 = 64;
+
+const US_PER_SECOND : u64 = 1_000_000;
+
+pub struct MidiPlayer {
+    pub pool : VoicePool,
+    /// patches selected by program change
+    pub programs : Vec<Voice>,
+    /// controllers to parameters, programs to slots of `programs`
+    pub map : MidiMap,
+    /// the channel listened to, None for all
+    pub channel : Option<u8>,
+    /// samples between an event's arrival and when it plays
+    pub latency : u64,
+
+    events : Receiver<TimedEvent>,
+    pending : Option<(u64, MidiEvent)>,
+    anchor : Option<i64>,
+    frame : u64,
+}
+
+impl MidiPlayer {
+    pub fn new(pool : VoicePool, events : Receiver<TimedEvent>) -> MidiPlayer {
+        MidiPlayer {
+            pool,
+            programs : Vec::new(),
+            map : MidiMap::new(),
+            channel : None,
+            latency : SAMPLE_FREQ as u64 / 100,
+
+            events,
+            pending : None,
+            anchor : None,
+            frame : 0,
+        }
+    }
+
+    pub fn get_sample(&mut self) -> f32 {
+        if self.frame.is_multiple_of(BLOCK_SIZE) && self.pending.is_none() {
+            self.pending = self.receive();
+        }
+        while let Some((at, event)) = self.pending {
+            if at > self.frame {
+                break;
+            }
+            self.handle(event);
+            self.pending = self.receive();
+        }
+
+        self.frame += 1;
+        self.pool.get_sample()
+    }
+
+    /// the next queued event and the sample it plays at
+    fn receive(&mut self) -> Option<(u64, MidiEvent)> {
+        let timed = self.events.try_recv().ok()?;
+        let now = self.frame as i64;
+        let time = (timed.time_us * SAMPLE_FREQ as u64 / US_PER_SECOND) as i64;
+        let ahead = now + self.latency as i64;
+
+        let mut at = time + *self.anchor.get_or_insert(ahead - time);
+        if at < now || at > ahead + BLOCK_SIZE as i64 {
+            self.anchor = Some(ahead - time);
+            at = ahead;
+        }
+        Some((at as u64, timed.event))
+    }
+
+    fn handle(&mut self, event : MidiEvent) {
+        if self.channel.is_some_and(|channel| channel != event.channel()) {
+            return;
+        }
+        match event {
+            MidiEvent::ProgramChange { program, .. } => {
+                if let Some(patch) = self.programs.get(self.map.slot(program)) {
+                    self.pool.set_patch(*patch);
+                }
+            }
+            MidiEvent::ControlChange { cc, value, .. } => {
+                if !self.map.control_change(cc, value, &mut self.pool) {
+                    event.apply(&mut self.pool);
+                }
+            }
+            _ => event.apply(&mut self.pool),
+        }
+    }
+}
+
+impl Iterator for MidiPlayer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        return Some(self.get_sample());
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Source for MidiPlayer {
+    fn channels(&self) -> u16 {
+        return 1;
+    }
+
+     fn sample_rate(&self) -> u32 {
+        return SAMPLE_FREQ;
+     }
+
+     fn current_frame_len(&self) -> Option<usize> {
+        return None;
+     }
+
+     fn total_duration(&self) -> Option<Duration> {
+        return None;
+     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    fn note_on(time_us : u64, key : u8) -> TimedEvent {
+        TimedEvent { time_us, event : MidiEvent::NoteOn { channel : 0, key, velocity : 100 } }
+    }
+
+    /// sample at which the pool starts sounding `key`
+    fn onset(player : &mut MidiPlayer, key : u8) -> u64 {
+        while !(0..4).any(|idx| player.pool.key(idx) == Some(key)) {
+            player.get_sample();
+        }
+        player.frame - 1
+    }
+
+    #[test]
+    fn test_timing() {
+        let (sender, receiver) = sync_channel(16);
+        let mut player = MidiPlayer::new(VoicePool::new(Voice::new()), receiver);
+        player.latency = 100;
+
+        for _ in 0..10 {
+            player.get_sample();
+        }
+        // arrives in the first block after 10 samples
+        sender.send(note_on(5_000_000, 60)).unwrap();
+        sender.send(note_on(5_001_000, 62)).unwrap();
+        sender.send(note_on(5_001_020, 64)).unwrap();
+
+        assert_eq!(onset(&mut player, 60), 64 + 100);
+        assert_eq!(onset(&mut player, 62), 64 + 100 + 48);
+        assert_eq!(onset(&mut player, 64), 64 + 100 + 48);
+
+        // an event from the past re-anchors and plays after the latency
+        // from the block it arrives in
+        while !player.frame.is_multiple_of(BLOCK_SIZE) {
+            player.get_sample();
+        }
+        sender.send(note_on(4_000_000, 67)).unwrap();
+        let frame = player.frame;
+        assert_eq!(onset(&mut player, 67), frame + 100);
+    }
+
+    #[test]
+    fn test_channel() {
+        let (sender, receiver) = sync_channel(16);
+        let mut player = MidiPlayer::new(VoicePool::new(Voice::new()), receiver);
+        player.channel = Some(1);
+
+        let mut patch = Voice::new();
+        patch.set_algorithm(7);
+        player.programs.push(Voice::new());
+        player.programs.push(patch);
+
+        sender.send(note_on(0, 60)).unwrap();
+        sender.send(TimedEvent { time_us : 0, event : MidiEvent::ProgramChange { channel : 1, program : 1 } }).unwrap();
+        for _ in 0..1000 {
+            player.get_sample();
+        }
+        assert_eq!(player.pool.key(0), None);
+        assert!(player.pool.voices().all(|voice| voice.algorithm() == Some(7)));
+    }
+}
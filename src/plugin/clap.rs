@@ -0,0 +1,743 @@
+//! CLAP
+//!
+//! the engine as a CLAP instrument: one note input taking CLAP notes and
+//! MIDI, one stereo output, every voice parameter automatable and the patch
+//! text as state. Events are applied at their sample within a block.
+//!
+//! Build with `cargo build --release -p beriq_fm_plugins --features clap`
+//! and install `target/release/libberiq_fm_plugins.so` as `beriq_fm.clap`.
+//!
+//! The main thread reads parameter values from atomics that the audio thread
+//! keeps up to date; a loaded state is left in the same atomics for the audio
+//! thread to pick up at the start of its next block.
+
+use std::cell::UnsafeCell;
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::*;
+use clap_sys::ext::audio_ports::*;
+use clap_sys::ext::note_ports::*;
+use clap_sys::ext::params::*;
+use clap_sys::ext::state::*;
+use clap_sys::factory::plugin_factory::*;
+use clap_sys::host::clap_host;
+use clap_sys::id::{clap_id, CLAP_INVALID_ID};
+use clap_sys::plugin::*;
+use clap_sys::plugin_features::*;
+use clap_sys::process::*;
+use clap_sys::stream::*;
+use clap_sys::string_sizes::{CLAP_NAME_SIZE, CLAP_PATH_SIZE};
+use clap_sys::version::CLAP_VERSION;
+
+use crate::synth::param::{Param, Unit};
+use crate::synth::patch;
+use crate::synth::voice::Voice;
+use super::Engine;
+
+const PLUGIN_ID : &CStr = c"beriq_fm";
+const STATE_CHUNK : usize = 1024;
+
+struct Features([*const c_char; 4]);
+
+unsafe impl Sync for Features {}
+
+static FEATURES : Features = Features([
+    CLAP_PLUGIN_FEATURE_INSTRUMENT.as_ptr(),
+    CLAP_PLUGIN_FEATURE_SYNTHESIZER.as_ptr(),
+    CLAP_PLUGIN_FEATURE_STEREO.as_ptr(),
+    ptr::null(),
+]);
+
+static DESCRIPTOR : clap_plugin_descriptor = clap_plugin_descriptor {
+    clap_version : CLAP_VERSION,
+    id : PLUGIN_ID.as_ptr(),
+    name : c"beriq FM".as_ptr(),
+    vendor : c"beriq_fm".as_ptr(),
+    url : c"".as_ptr(),
+    manual_url : c"".as_ptr(),
+    support_url : c"".as_ptr(),
+    version : concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+    description : c"FM synthesizer on 16.16 fixpoint arithmetic".as_ptr(),
+    features : &FEATURES.0 as *const *const c_char,
+};
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static clap_entry : clap_plugin_entry = clap_plugin_entry {
+    clap_version : CLAP_VERSION,
+    init : Some(entry_init),
+    deinit : Some(entry_deinit),
+    get_factory : Some(entry_get_factory),
+};
+
+static FACTORY : clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count : Some(factory_count),
+    get_plugin_descriptor : Some(factory_descriptor),
+    create_plugin : Some(factory_create),
+};
+
+static PARAMS : clap_plugin_params = clap_plugin_params {
+    count : Some(params_count),
+    get_info : Some(params_info),
+    get_value : Some(params_value),
+    value_to_text : Some(params_value_to_text),
+    text_to_value : Some(params_text_to_value),
+    flush : Some(params_flush),
+};
+
+static STATE : clap_plugin_state = clap_plugin_state {
+    save : Some(state_save),
+    load : Some(state_load),
+};
+
+static AUDIO_PORTS : clap_plugin_audio_ports = clap_plugin_audio_ports {
+    count : Some(audio_ports_count),
+    get : Some(audio_ports_get),
+};
+
+static NOTE_PORTS : clap_plugin_note_ports = clap_plugin_note_ports {
+    count : Some(note_ports_count),
+    get : Some(note_ports_get),
+};
+
+struct Plugin {
+    raw : clap_plugin,
+    host : *const clap_host,
+    /// only touched from the audio thread, or while it is not processing
+    engine : UnsafeCell<Engine>,
+    params : Vec<Param>,
+    ids : Vec<clap_id>,
+    /// parameter values for the main thread, `f32` bits in `params` order
+    values : Vec<AtomicU32>,
+    /// `values` hold a loaded state that the engine has yet to take
+    reload : AtomicBool,
+}
+
+impl Plugin {
+    fn new(host : *const clap_host) -> Box<Plugin> {
+        let patch = Voice::new();
+        let params : Vec<Param> = Param::all().collect();
+        let mut plugin = Box::new(Plugin {
+            raw : clap_plugin {
+                desc : &DESCRIPTOR,
+                plugin_data : ptr::null_mut(),
+                init : Some(plugin_init),
+                destroy : Some(plugin_destroy),
+                activate : Some(plugin_activate),
+                deactivate : Some(plugin_deactivate),
+                start_processing : Some(plugin_start_processing),
+                stop_processing : Some(plugin_stop_processing),
+                reset : Some(plugin_reset),
+                process : Some(plugin_process),
+                get_extension : Some(plugin_get_extension),
+                on_main_thread : Some(plugin_on_main_thread),
+            },
+            host,
+            engine : UnsafeCell::new(Engine::new(patch)),
+            ids : params.iter().map(|p| p.id()).collect(),
+            values : params.iter().map(|p| AtomicU32::new(p.get(&patch).to_bits())).collect(),
+            params,
+            reload : AtomicBool::new(false),
+        });
+        plugin.raw.plugin_data = &mut *plugin as *mut Plugin as *mut c_void;
+        plugin
+    }
+
+    unsafe fn from_raw<'a>(raw : *const clap_plugin) -> &'a Plugin {
+        &*((*raw).plugin_data as *const Plugin)
+    }
+
+    /// the engine; callers are on the audio thread or it is not processing
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn engine(&self) -> &mut Engine {
+        &mut *self.engine.get()
+    }
+
+    fn index(&self, id : clap_id) -> Option<usize> {
+        self.ids.iter().position(|i| *i == id)
+    }
+
+    fn value(&self, idx : usize) -> f32 {
+        f32::from_bits(self.values[idx].load(Ordering::Relaxed))
+    }
+
+    /// the patch as the main thread sees it
+    fn patch(&self) -> Voice {
+        let mut patch = Voice::new();
+        for (idx, param) in self.params.iter().enumerate() {
+            param.set(&mut patch, self.value(idx));
+        }
+        patch
+    }
+
+    fn set_patch(&self, patch : &Voice) {
+        for (param, value) in self.params.iter().zip(&self.values) {
+            value.store(param.get(patch).to_bits(), Ordering::Relaxed);
+        }
+        self.reload.store(true, Ordering::Release);
+    }
+
+    /// give the engine a state loaded since the last block
+    fn take_state(&self, engine : &mut Engine) {
+        if self.reload.swap(false, Ordering::Acquire) {
+            engine.set_patch(self.patch());
+        }
+    }
+
+    /// store the values the engine has changed and tell the host with
+    /// events at `time` on `out`
+    unsafe fn publish(&self, engine : &Engine, time : u32, out : *const clap_output_events) {
+        let push = out.as_ref().and_then(|out| out.try_push);
+        for (idx, param) in self.params.iter().enumerate() {
+            let value = param.get(engine.patch());
+            if value.to_bits() == self.values[idx].load(Ordering::Relaxed) {
+                continue;
+            }
+            self.values[idx].store(value.to_bits(), Ordering::Relaxed);
+            if let Some(push) = push {
+                let event = clap_event_param_value {
+                    header : clap_event_header {
+                        size : size_of::<clap_event_param_value>() as u32,
+                        time,
+                        space_id : CLAP_CORE_EVENT_SPACE_ID,
+                        type_ : CLAP_EVENT_PARAM_VALUE,
+                        flags : 0,
+                    },
+                    param_id : self.ids[idx], cookie : ptr::null_mut(), note_id : -1,
+                    port_index : -1, channel : -1, key : -1, value : value as f64,
+                };
+                push(out, &event.header);
+            }
+        }
+    }
+
+    unsafe fn handle_event(&self, engine : &mut Engine, header : *const clap_event_header,
+        out : *const clap_output_events) {
+        if (*header).space_id != CLAP_CORE_EVENT_SPACE_ID {
+            return;
+        }
+        match (*header).type_ {
+            CLAP_EVENT_NOTE_ON => {
+                let note = &*(header as *const clap_event_note);
+                if (0..128).contains(&note.key) {
+                    let velocity = (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+                    engine.pool.note_on(note.key as u8, velocity);
+                }
+            }
+            CLAP_EVENT_NOTE_OFF | CLAP_EVENT_NOTE_CHOKE => {
+                let note = &*(header as *const clap_event_note);
+                match note.key {
+                    0..=127 => engine.pool.note_off(note.key as u8),
+                    // -1 is every key
+                    _ => engine.pool.all_notes_off(),
+                }
+            }
+            CLAP_EVENT_PARAM_VALUE => {
+                let event = &*(header as *const clap_event_param_value);
+                if let Some(idx) = self.index(event.param_id) {
+                    let param = self.params[idx];
+                    engine.set_param(param, event.value as f32);
+                    self.values[idx].store(param.get(engine.patch()).to_bits(), Ordering::Relaxed);
+                    // a preset replaces the routing, which the host has to see
+                    if param == Param::Algorithm {
+                        self.publish(engine, (*header).time, out);
+                    }
+                }
+            }
+            CLAP_EVENT_MIDI => {
+                let event = &*(header as *const clap_event_midi);
+                engine.midi(&event.data);
+            }
+            _ => (),
+        }
+    }
+
+    /// ask the host to read all parameter values again
+    unsafe fn rescan_params(&self) {
+        let host = &*self.host;
+        let params = match host.get_extension {
+            Some(get_extension) => get_extension(self.host, CLAP_EXT_PARAMS.as_ptr()) as *const clap_host_params,
+            None => return,
+        };
+        if let Some(rescan) = params.as_ref().and_then(|params| params.rescan) {
+            rescan(self.host, CLAP_PARAM_RESCAN_VALUES);
+        }
+    }
+}
+
+/// `text` as a C string in `capacity` bytes at `dest`, cut off if needed
+unsafe fn copy_str(dest : *mut c_char, capacity : usize, text : &str) {
+    if capacity == 0 {
+        return;
+    }
+    let len = text.len().min(capacity - 1);
+    ptr::copy_nonoverlapping(text.as_ptr() as *const c_char, dest, len);
+    *dest.add(len) = 0;
+}
+
+unsafe fn events(list : *const clap_input_events) -> impl Iterator<Item = *const clap_event_header> {
+    let (size, get) = match list.as_ref() {
+        Some(&clap_input_events { size : Some(size), get : Some(get), .. }) => (size(list), Some(get)),
+        _ => (0, None),
+    };
+    (0..size).filter_map(move |idx| get.map(|get| get(list, idx))).filter(|header| !header.is_null())
+}
+
+unsafe extern "C" fn entry_init(_plugin_path : *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {}
+
+unsafe extern "C" fn entry_get_factory(factory_id : *const c_char) -> *const c_void {
+    if !factory_id.is_null() && CStr::from_ptr(factory_id) == CLAP_PLUGIN_FACTORY_ID {
+        &FACTORY as *const clap_plugin_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_count(_factory : *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_descriptor(_factory : *const clap_plugin_factory, index : u32)
+    -> *const clap_plugin_descriptor {
+    if index == 0 { &DESCRIPTOR } else { ptr::null() }
+}
+
+unsafe extern "C" fn factory_create(_factory : *const clap_plugin_factory, host : *const clap_host,
+    plugin_id : *const c_char) -> *const clap_plugin {
+    if host.is_null() || plugin_id.is_null() || CStr::from_ptr(plugin_id) != PLUGIN_ID {
+        return ptr::null();
+    }
+    let plugin = Box::into_raw(Plugin::new(host));
+    &(*plugin).raw
+}
+
+unsafe extern "C" fn plugin_init(_plugin : *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin : *const clap_plugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut Plugin));
+}
+
+unsafe extern "C" fn plugin_activate(plugin : *const clap_plugin, sample_rate : f64,
+    _min_frames : u32, _max_frames : u32) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    let engine = plugin.engine();
+    plugin.take_state(engine);
+    engine.set_sample_rate(sample_rate.round() as u32);
+    engine.reset();
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(_plugin : *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_start_processing(_plugin : *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin : *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_reset(plugin : *const clap_plugin) {
+    Plugin::from_raw(plugin).engine().reset();
+}
+
+unsafe extern "C" fn plugin_process(plugin : *const clap_plugin, process : *const clap_process)
+    -> clap_process_status {
+    let plugin = Plugin::from_raw(plugin);
+    let engine = plugin.engine();
+    let process = &*process;
+    plugin.take_state(engine);
+
+    let output = match process.audio_outputs_count {
+        0 => None,
+        _ => process.audio_outputs.as_ref(),
+    };
+    let channels = match output {
+        Some(output) if !output.data32.is_null() =>
+            slice::from_raw_parts(output.data32, output.channel_count as usize),
+        _ => &[],
+    };
+    let frames = process.frames_count as usize;
+    let out : &mut [f32] = match channels.first() {
+        Some(channel) if !channel.is_null() => slice::from_raw_parts_mut(*channel, frames),
+        _ => &mut [],
+    };
+
+    // render up to each event, then apply it
+    let mut done = 0;
+    for header in events(process.in_events) {
+        let time = ((*header).time as usize).clamp(done, out.len());
+        engine.render(&mut out[done..time]);
+        done = time;
+        plugin.handle_event(engine, header, process.out_events);
+    }
+    engine.render(&mut out[done..]);
+
+    for channel in channels.iter().skip(1).filter(|channel| !channel.is_null()) {
+        ptr::copy_nonoverlapping(out.as_ptr(), *channel, out.len());
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe extern "C" fn plugin_get_extension(_plugin : *const clap_plugin, id : *const c_char) -> *const c_void {
+    if id.is_null() {
+        return ptr::null();
+    }
+    let id = CStr::from_ptr(id);
+    if id == CLAP_EXT_PARAMS {
+        &PARAMS as *const clap_plugin_params as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const clap_plugin_state as *const c_void
+    } else if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const clap_plugin_audio_ports as *const c_void
+    } else if id == CLAP_EXT_NOTE_PORTS {
+        &NOTE_PORTS as *const clap_plugin_note_ports as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin : *const clap_plugin) {}
+
+unsafe extern "C" fn params_count(plugin : *const clap_plugin) -> u32 {
+    Plugin::from_raw(plugin).params.len() as u32
+}
+
+unsafe extern "C" fn params_info(plugin : *const clap_plugin, index : u32, info : *mut clap_param_info) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    let Some(param) = plugin.params.get(index as usize) else {
+        return false;
+    };
+    let meta = param.info();
+
+    let mut flags = CLAP_PARAM_IS_AUTOMATABLE;
+    if meta.stepped {
+        flags |= CLAP_PARAM_IS_STEPPED;
+    }
+    if matches!(meta.unit, Unit::Choice(_)) {
+        flags |= CLAP_PARAM_IS_ENUM;
+    }
+
+    let info = &mut *info;
+    info.id = meta.id;
+    info.flags = flags;
+    info.cookie = ptr::null_mut();
+    copy_str(info.name.as_mut_ptr(), CLAP_NAME_SIZE, &param.display_name());
+    copy_str(info.module.as_mut_ptr(), CLAP_PATH_SIZE, &param.module());
+    info.min_value = meta.min as f64;
+    info.max_value = meta.max as f64;
+    info.default_value = meta.default as f64;
+    true
+}
+
+unsafe extern "C" fn params_value(plugin : *const clap_plugin, id : clap_id, value : *mut f64) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    match plugin.index(id) {
+        Some(idx) => {
+            *value = plugin.value(idx) as f64;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_value_to_text(plugin : *const clap_plugin, id : clap_id, value : f64,
+    buffer : *mut c_char, capacity : u32) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    match plugin.index(id) {
+        Some(idx) => {
+            copy_str(buffer, capacity as usize, &plugin.params[idx].display(value as f32));
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_text_to_value(plugin : *const clap_plugin, id : clap_id, text : *const c_char,
+    value : *mut f64) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    let parsed = plugin.index(id)
+        .zip(CStr::from_ptr(text).to_str().ok())
+        .and_then(|(idx, text)| plugin.params[idx].parse(text));
+    match parsed {
+        Some(parsed) => {
+            *value = parsed as f64;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_flush(plugin : *const clap_plugin, in_events : *const clap_input_events,
+    out_events : *const clap_output_events) {
+    let plugin = Plugin::from_raw(plugin);
+    let engine = plugin.engine();
+    plugin.take_state(engine);
+    for header in events(in_events) {
+        plugin.handle_event(engine, header, out_events);
+    }
+}
+
+unsafe extern "C" fn state_save(plugin : *const clap_plugin, stream : *const clap_ostream) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    let Some(write) = (*stream).write else {
+        return false;
+    };
+    let text = patch::to_text(&plugin.patch());
+    let mut bytes = text.as_bytes();
+    while !bytes.is_empty() {
+        let written = write(stream, bytes.as_ptr() as *const c_void, bytes.len() as u64);
+        if written <= 0 {
+            return false;
+        }
+        bytes = &bytes[written as usize..];
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin : *const clap_plugin, stream : *const clap_istream) -> bool {
+    let plugin = Plugin::from_raw(plugin);
+    let Some(read) = (*stream).read else {
+        return false;
+    };
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; STATE_CHUNK];
+    loop {
+        let count = read(stream, chunk.as_mut_ptr() as *mut c_void, chunk.len() as u64);
+        if count < 0 {
+            return false;
+        }
+        if count == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..count as usize]);
+    }
+
+    let patch = match std::str::from_utf8(&bytes).ok().map(patch::parse) {
+        Some(Ok(patch)) => patch,
+        _ => return false,
+    };
+    plugin.set_patch(&patch);
+    plugin.rescan_params();
+    true
+}
+
+unsafe extern "C" fn audio_ports_count(_plugin : *const clap_plugin, is_input : bool) -> u32 {
+    if is_input { 0 } else { 1 }
+}
+
+unsafe extern "C" fn audio_ports_get(_plugin : *const clap_plugin, index : u32, is_input : bool,
+    info : *mut clap_audio_port_info) -> bool {
+    if is_input || index != 0 {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    copy_str(info.name.as_mut_ptr(), CLAP_NAME_SIZE, "Output");
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = 2;
+    info.port_type = CLAP_PORT_STEREO.as_ptr();
+    info.in_place_pair = CLAP_INVALID_ID;
+    true
+}
+
+unsafe extern "C" fn note_ports_count(_plugin : *const clap_plugin, is_input : bool) -> u32 {
+    if is_input { 1 } else { 0 }
+}
+
+unsafe extern "C" fn note_ports_get(_plugin : *const clap_plugin, index : u32, is_input : bool,
+    info : *mut clap_note_port_info) -> bool {
+    if !is_input || index != 0 {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    info.supported_dialects = CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI;
+    info.preferred_dialect = CLAP_NOTE_DIALECT_CLAP;
+    copy_str(info.name.as_mut_ptr(), CLAP_NAME_SIZE, "Notes");
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap_sys::audio_buffer::clap_audio_buffer;
+
+    fn host() -> clap_host {
+        clap_host {
+            clap_version : CLAP_VERSION,
+            host_data : ptr::null_mut(),
+            name : c"test".as_ptr(),
+            vendor : c"test".as_ptr(),
+            url : c"".as_ptr(),
+            version : c"1".as_ptr(),
+            get_extension : None,
+            request_restart : None,
+            request_process : None,
+            request_callback : None,
+        }
+    }
+
+    unsafe fn create(host : &clap_host) -> *const clap_plugin {
+        let factory = (clap_entry.get_factory.unwrap())(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory;
+        assert_eq!(((*factory).get_plugin_count.unwrap())(factory), 1);
+        let plugin = ((*factory).create_plugin.unwrap())(factory, host, PLUGIN_ID.as_ptr());
+        assert!(((*plugin).init.unwrap())(plugin));
+        plugin
+    }
+
+    struct Events(Vec<*const clap_event_header>);
+
+    unsafe extern "C" fn events_size(list : *const clap_input_events) -> u32 {
+        (&*((*list).ctx as *const Events)).0.len() as u32
+    }
+
+    unsafe extern "C" fn events_get(list : *const clap_input_events, idx : u32) -> *const clap_event_header {
+        (&*((*list).ctx as *const Events)).0[idx as usize]
+    }
+
+    unsafe extern "C" fn stream_write(stream : *const clap_ostream, buffer : *const c_void, size : u64) -> i64 {
+        // a few bytes at a time, as streams may do
+        let size = size.min(7) as usize;
+        let bytes = &mut *((*stream).ctx as *mut Vec<u8>);
+        bytes.extend_from_slice(slice::from_raw_parts(buffer as *const u8, size));
+        size as i64
+    }
+
+    unsafe extern "C" fn stream_read(stream : *const clap_istream, buffer : *mut c_void, size : u64) -> i64 {
+        let bytes = &mut *((*stream).ctx as *mut Vec<u8>);
+        let size = (size as usize).min(bytes.len());
+        ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, size);
+        bytes.drain(..size);
+        size as i64
+    }
+
+    /// parameter values pushed by the plugin
+    struct Output(Vec<(clap_id, f64)>);
+
+    unsafe extern "C" fn output_push(list : *const clap_output_events, header : *const clap_event_header) -> bool {
+        let event = &*(header as *const clap_event_param_value);
+        (&mut *((*list).ctx as *mut Output)).0.push((event.param_id, event.value));
+        true
+    }
+
+    fn header(time : u32, type_ : u16, size : usize) -> clap_event_header {
+        clap_event_header { size : size as u32, time, space_id : CLAP_CORE_EVENT_SPACE_ID, type_, flags : 0 }
+    }
+
+    #[test]
+    fn test_plugin() {
+        const FRAMES : usize = 256;
+        let host = host();
+        unsafe {
+            let plugin = create(&host);
+            let params = ((*plugin).get_extension.unwrap())(plugin, CLAP_EXT_PARAMS.as_ptr()) as *const clap_plugin_params;
+            assert_eq!(((*params).count.unwrap())(plugin) as usize, Param::all().count());
+
+            let tune = Param::Op(1, crate::synth::param::OpParam::Tune);
+            let mut text = [0 as c_char; 64];
+            assert!(((*params).value_to_text.unwrap())(plugin, tune.id(), 0.5, text.as_mut_ptr(), 64));
+            assert_eq!(CStr::from_ptr(text.as_ptr()).to_str(), Ok("+0.5000 oct"));
+
+            let feedback = Param::all().position(|p| p == Param::Feedback(3, 0)).unwrap();
+            let mut info : clap_param_info = std::mem::zeroed();
+            assert!(((*params).get_info.unwrap())(plugin, feedback as u32, &mut info));
+            assert_eq!(CStr::from_ptr(info.name.as_ptr()).to_str(), Ok("Feedback 4>1"));
+            assert_eq!(CStr::from_ptr(info.module.as_ptr()).to_str(), Ok("routing"));
+
+            assert!(((*plugin).activate.unwrap())(plugin, 44100.0, 1, FRAMES as u32));
+            let note = clap_event_note {
+                header : header(10, CLAP_EVENT_NOTE_ON, size_of::<clap_event_note>()),
+                note_id : -1, port_index : 0, channel : 0, key : 60, velocity : 1.0,
+            };
+            let value = clap_event_param_value {
+                header : header(10, CLAP_EVENT_PARAM_VALUE, size_of::<clap_event_param_value>()),
+                param_id : tune.id(), cookie : ptr::null_mut(), note_id : -1,
+                port_index : -1, channel : -1, key : -1, value : 0.5,
+            };
+            let events = Events(vec![&note.header, &value.header]);
+            let in_events = clap_input_events {
+                ctx : &events as *const Events as *mut c_void,
+                size : Some(events_size),
+                get : Some(events_get),
+            };
+
+            let mut left = [1.0f32; FRAMES];
+            let mut right = [1.0f32; FRAMES];
+            let mut data = [left.as_mut_ptr(), right.as_mut_ptr()];
+            let mut output = clap_audio_buffer {
+                data32 : data.as_mut_ptr(),
+                data64 : ptr::null_mut(),
+                channel_count : 2,
+                latency : 0,
+                constant_mask : 0,
+            };
+            let process = clap_process {
+                steady_time : 0,
+                frames_count : FRAMES as u32,
+                transport : ptr::null(),
+                audio_inputs : ptr::null(),
+                audio_outputs : &mut output,
+                audio_inputs_count : 0,
+                audio_outputs_count : 1,
+                in_events : &in_events,
+                out_events : ptr::null(),
+            };
+            assert_eq!(((*plugin).process.unwrap())(plugin, &process), CLAP_PROCESS_CONTINUE);
+            assert!(left[..10].iter().all(|s| *s == 0.0));
+            assert!(left[10..].iter().any(|s| *s != 0.0));
+            assert_eq!(left, right);
+
+            let mut out = 0.0;
+            assert!(((*params).get_value.unwrap())(plugin, tune.id(), &mut out));
+            assert_eq!(out, 0.5);
+
+            // the state carries over to another instance
+            let state = ((*plugin).get_extension.unwrap())(plugin, CLAP_EXT_STATE.as_ptr()) as *const clap_plugin_state;
+            let mut bytes = Vec::new();
+            let ostream = clap_ostream { ctx : &mut bytes as *mut Vec<u8> as *mut c_void, write : Some(stream_write) };
+            assert!(((*state).save.unwrap())(plugin, &ostream));
+            let saved = String::from_utf8(bytes.clone()).unwrap();
+
+            let other = create(&host);
+            let istream = clap_istream { ctx : &mut bytes as *mut Vec<u8> as *mut c_void, read : Some(stream_read) };
+            assert!(((*state).load.unwrap())(other, &istream));
+            assert!(((*params).get_value.unwrap())(other, tune.id(), &mut out));
+            assert_eq!(out, 0.5);
+            assert_eq!(patch::to_text(&Plugin::from_raw(other).patch()).as_str(), saved);
+
+            // the routing of a preset reaches the host with the algorithm
+            let algorithm = clap_event_param_value {
+                header : header(0, CLAP_EVENT_PARAM_VALUE, size_of::<clap_event_param_value>()),
+                param_id : Param::Algorithm.id(), cookie : ptr::null_mut(), note_id : -1,
+                port_index : -1, channel : -1, key : -1, value : 7.0,
+            };
+            let events = Events(vec![&algorithm.header]);
+            let in_events = clap_input_events {
+                ctx : &events as *const Events as *mut c_void,
+                size : Some(events_size),
+                get : Some(events_get),
+            };
+            let mut pushed = Output(Vec::new());
+            let out_events = clap_output_events { ctx : &mut pushed as *mut Output as *mut c_void, try_push : Some(output_push) };
+            ((*params).flush.unwrap())(plugin, &in_events, &out_events);
+            // the stack's three modulations off, ops 1 to 3 heard
+            assert_eq!(pushed.0.len(), 3 + 3);
+            assert!(pushed.0.contains(&(Param::Mod(2, 3).id(), 0.0)));
+            assert!(pushed.0.contains(&(Param::Op(0, crate::synth::param::OpParam::Output).id(), 1.0)));
+            assert!(((*params).get_value.unwrap())(plugin, Param::Mod(2, 3).id(), &mut out));
+            assert_eq!(out, 0.0);
+
+            ((*plugin).destroy.unwrap())(plugin);
+            ((*other).destroy.unwrap())(other);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! ffi
+//!
+//! C API of the engine, declared in `include/beriq_fm.h`. The `BeriqEngine`
+//! of the header is an `Engine` on the heap; all functions take a null
+//! engine without harm.
+
+use std::slice;
+
+use crate::synth::param::Param;
+use crate::synth::patch;
+use crate::synth::voice::Voice;
+use super::Engine;
+
+pub const BERIQ_OK : i32 = 0;
+pub const BERIQ_ERROR_NULL : i32 = -1;
+pub const BERIQ_ERROR_PATCH : i32 = -2;
+pub const BERIQ_ERROR_PARAM : i32 = -3;
+
+/// frames rendered at a time before interleaving
+const CHUNK : usize = 64;
+
+#[no_mangle]
+pub extern "C" fn beriq_engine_new(sample_rate : u32) -> *mut Engine {
+    let mut engine = Engine::new(Voice::new());
+    engine.set_sample_rate(sample_rate);
+    Box::into_raw(Box::new(engine))
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`, and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_free(engine : *mut Engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`; `data` points to `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_load_patch(engine : *mut Engine, data : *const u8, len : usize) -> i32 {
+    let Some(engine) = engine.as_mut() else {
+        return BERIQ_ERROR_NULL;
+    };
+    if data.is_null() {
+        return BERIQ_ERROR_NULL;
+    }
+    let parsed = std::str::from_utf8(slice::from_raw_parts(data, len)).ok().map(patch::parse);
+    match parsed {
+        Some(Ok(patch)) => {
+            engine.set_patch(patch);
+            BERIQ_OK
+        }
+        _ => BERIQ_ERROR_PATCH,
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_set_param(engine : *mut Engine, id : u32, value : f32) -> i32 {
+    let Some(engine) = engine.as_mut() else {
+        return BERIQ_ERROR_NULL;
+    };
+    match Param::from_id(id) {
+        Some(param) => {
+            engine.set_param(param, value);
+            BERIQ_OK
+        }
+        None => BERIQ_ERROR_PARAM,
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`; `value` is null or writable
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_get_param(engine : *const Engine, id : u32, value : *mut f32) -> i32 {
+    let (Some(engine), Some(value)) = (engine.as_ref(), value.as_mut()) else {
+        return BERIQ_ERROR_NULL;
+    };
+    match Param::from_id(id) {
+        Some(param) => {
+            *value = param.get(engine.patch());
+            BERIQ_OK
+        }
+        None => BERIQ_ERROR_PARAM,
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_note_on(engine : *mut Engine, key : u8, velocity : u8) {
+    if let Some(engine) = engine.as_mut() {
+        engine.pool.note_on(key, velocity);
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_note_off(engine : *mut Engine, key : u8) {
+    if let Some(engine) = engine.as_mut() {
+        engine.pool.note_off(key);
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_all_notes_off(engine : *mut Engine) {
+    if let Some(engine) = engine.as_mut() {
+        engine.pool.all_notes_off();
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`; `data` points to `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_midi(engine : *mut Engine, data : *const u8, len : usize) {
+    if let (Some(engine), false) = (engine.as_mut(), data.is_null()) {
+        engine.midi(slice::from_raw_parts(data, len));
+    }
+}
+
+/// # Safety
+/// `engine` is null or from `beriq_engine_new`; `out` has room for
+/// `frames * channels` samples
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_render_f32(engine : *mut Engine, out : *mut f32, frames : usize, channels : u32) {
+    render(engine, out, frames, channels, |sample| sample);
+}
+
+/// # Safety
+/// as `beriq_engine_render_f32`
+#[no_mangle]
+pub unsafe extern "C" fn beriq_engine_render_i16(engine : *mut Engine, out : *mut i16, frames : usize, channels : u32) {
+    render(engine, out, frames, channels, |sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+}
+
+unsafe fn render<T : Copy>(engine : *mut Engine, out : *mut T, frames : usize, channels : u32,
+    convert : impl Fn(f32) -> T) {
+    let Some(engine) = engine.as_mut() else {
+        return;
+    };
+    if out.is_null() || channels == 0 {
+        return;
+    }
+    let channels = channels as usize;
+    let out = slice::from_raw_parts_mut(out, frames * channels);
+
+    let mut chunk = [0.0; CHUNK];
+    for frames in out.chunks_mut(CHUNK * channels) {
+        let mono = &mut chunk[..frames.len() / channels];
+        engine.render(mono);
+        for (frame, sample) in frames.chunks_exact_mut(channels).zip(mono.iter()) {
+            frame.fill(convert(*sample));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+    use crate::synth::param::OpParam;
+
+    const HEADER : &str = include_str!("../../include/beriq_fm.h");
+
+    #[test]
+    fn test_header() {
+        for (name, value) in [("BERIQ_OK", BERIQ_OK), ("BERIQ_ERROR_NULL", BERIQ_ERROR_NULL),
+            ("BERIQ_ERROR_PATCH", BERIQ_ERROR_PATCH), ("BERIQ_ERROR_PARAM", BERIQ_ERROR_PARAM)] {
+            assert!(HEADER.lines().any(|l| l.split_whitespace().eq([
+                "#define", name, &value.to_string()])), "{name}");
+        }
+
+        // parameter ids as in the registry
+        for param in Param::all() {
+            let (define, id) = match param {
+                Param::Op(0, _) => (format!("BERIQ_OP_{}", param.info().key), param.id() - 100),
+                Param::Op(..) | Param::Mod(..) | Param::Feedback(..) => continue,
+                _ => (format!("BERIQ_PARAM_{}", param.info().key), param.id()),
+            };
+            let define = define.to_uppercase();
+            assert!(HEADER.lines().any(|l| l.split_whitespace().eq([
+                "#define", define.as_str(), &id.to_string()])), "{define}");
+        }
+        assert!(HEADER.contains("#define BERIQ_PARAM_OP(op, field) ((op) * 100 + (field))"));
+        assert!(HEADER.contains("#define BERIQ_PARAM_MOD(src, dst) ((src) * 10 + (dst))"));
+        assert_eq!(Param::Mod(0, 2).id(), 13);
+        assert!(HEADER.contains("#define BERIQ_PARAM_FEEDBACK(src, dst) ((src) * 10 + (dst))"));
+        assert_eq!(Param::Feedback(3, 0).id(), 41);
+
+        for function in ["beriq_engine_new", "beriq_engine_free", "beriq_engine_load_patch",
+            "beriq_engine_set_param", "beriq_engine_get_param", "beriq_engine_note_on",
+            "beriq_engine_note_off", "beriq_engine_all_notes_off", "beriq_engine_midi",
+            "beriq_engine_render_f32", "beriq_engine_render_i16"] {
+            assert!(HEADER.contains(&format!(" *{function}(")) || HEADER.contains(&format!(" {function}(")),
+                "{function}");
+        }
+    }
+
+    #[test]
+    fn test_engine() {
+        unsafe {
+            let engine = beriq_engine_new(48000);
+            let patch = b"algorithm = 2\nop2.total_level = 12\nop4.attack_rate = 1\n";
+            assert_eq!(beriq_engine_load_patch(engine, patch.as_ptr(), patch.len()), BERIQ_OK);
+            assert_eq!(beriq_engine_load_patch(engine, b"op9.x = 1".as_ptr(), 9), BERIQ_ERROR_PATCH);
+            assert_eq!(beriq_engine_load_patch(ptr::null_mut(), patch.as_ptr(), patch.len()), BERIQ_ERROR_NULL);
+
+            let tune = Param::Op(1, OpParam::Tune).id();
+            let mut value = 0.0;
+            assert_eq!(beriq_engine_set_param(engine, tune, 1.5), BERIQ_OK);
+            assert_eq!(beriq_engine_get_param(engine, tune, &mut value), BERIQ_OK);
+            assert_eq!(value, 1.5);
+            assert_eq!(beriq_engine_get_param(engine, Param::Algorithm.id(), &mut value), BERIQ_OK);
+            assert_eq!(value, 2.0);
+            assert_eq!(beriq_engine_set_param(engine, 99, 1.0), BERIQ_ERROR_PARAM);
+
+            // both formats and any channel count carry the same signal
+            let reference = beriq_engine_new(48000);
+            beriq_engine_load_patch(reference, patch.as_ptr(), patch.len());
+            beriq_engine_set_param(reference, tune, 1.5);
+            beriq_engine_note_on(engine, 60, 100);
+            beriq_engine_midi(reference, [0x90, 60, 100].as_ptr(), 3);
+
+            const FRAMES : usize = 150;
+            let mut mono = [0.0f32; FRAMES];
+            let mut stereo = [0i16; FRAMES * 2];
+            beriq_engine_render_f32(reference, mono.as_mut_ptr(), FRAMES, 1);
+            beriq_engine_render_i16(engine, stereo.as_mut_ptr(), FRAMES, 2);
+            assert!(mono.iter().any(|s| s.abs() > 0.1));
+            for (frame, sample) in stereo.chunks(2).zip(mono) {
+                assert_eq!(frame, [(sample.clamp(-1.0, 1.0) * 32767.0) as i16; 2]);
+            }
+
+            beriq_engine_note_off(engine, 60);
+            beriq_engine_all_notes_off(engine);
+            beriq_engine_free(engine);
+            beriq_engine_free(reference);
+            beriq_engine_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! LV2
+//!
+//! the engine as an LV2 instrument: an atom port taking MIDI, a stereo pair
+//! of audio outputs and one control port per voice parameter but the
+//! algorithm. Setting the algorithm replaces the routing, and a plugin
+//! cannot write the host's routing ports back, so the algorithms are
+//! presets of those ports instead. The TTL metadata is generated from the
+//! parameter registry, so the bundle always matches the library:
+//!
+//! ```text
+//! cargo build --release -p beriq_fm_plugins --features lv2
+//! cargo run --example lv2_bundle --features lv2 -- target/beriq_fm.lv2
+//! ```
+//!
+//! MIDI is applied at its frame within a block, control ports once per block.
+
+use std::ffi::{c_char, c_void, CStr};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::synth::param::{OpParam, Param, Unit};
+use crate::synth::voice::{Voice, ALGORITHM_COUNT};
+use super::Engine;
+
+pub const PLUGIN_URI : &str = "urn:beriq_fm:fm";
+const PLUGIN_URI_C : &CStr = c"urn:beriq_fm:fm";
+const URID_MAP_URI : &CStr = c"http://lv2plug.in/ns/ext/urid#map";
+const MIDI_EVENT_URI : &CStr = c"http://lv2plug.in/ns/ext/midi#MidiEvent";
+
+pub const PORT_MIDI_IN : u32 = 0;
+pub const PORT_OUT_LEFT : u32 = 1;
+pub const PORT_OUT_RIGHT : u32 = 2;
+/// control ports follow in `ports()` order
+pub const PORT_PARAMS : u32 = 3;
+
+type LV2Handle = *mut c_void;
+type LV2Urid = u32;
+
+#[repr(C)]
+pub struct LV2Feature {
+    pub uri : *const c_char,
+    pub data : *mut c_void,
+}
+
+#[repr(C)]
+pub struct LV2UridMap {
+    pub handle : *mut c_void,
+    pub map : Option<unsafe extern "C" fn(handle : *mut c_void, uri : *const c_char) -> LV2Urid>,
+}
+
+#[repr(C)]
+pub struct LV2Descriptor {
+    pub uri : *const c_char,
+    pub instantiate : Option<unsafe extern "C" fn(descriptor : *const LV2Descriptor, sample_rate : f64,
+        bundle_path : *const c_char, features : *const *const LV2Feature) -> LV2Handle>,
+    pub connect_port : Option<unsafe extern "C" fn(instance : LV2Handle, port : u32, data : *mut c_void)>,
+    pub activate : Option<unsafe extern "C" fn(instance : LV2Handle)>,
+    pub run : Option<unsafe extern "C" fn(instance : LV2Handle, sample_count : u32)>,
+    pub deactivate : Option<unsafe extern "C" fn(instance : LV2Handle)>,
+    pub cleanup : Option<unsafe extern "C" fn(instance : LV2Handle)>,
+    pub extension_data : Option<unsafe extern "C" fn(uri : *const c_char) -> *const c_void>,
+}
+
+unsafe impl Sync for LV2Descriptor {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LV2Atom {
+    pub size : u32,
+    pub type_ : u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LV2AtomSequence {
+    pub atom : LV2Atom,
+    pub unit : u32,
+    pub pad : u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LV2AtomEvent {
+    pub frames : i64,
+    pub body : LV2Atom,
+}
+
+static DESCRIPTOR : LV2Descriptor = LV2Descriptor {
+    uri : PLUGIN_URI_C.as_ptr(),
+    instantiate : Some(instantiate),
+    connect_port : Some(connect_port),
+    activate : Some(activate),
+    run : Some(run),
+    deactivate : Some(deactivate),
+    cleanup : Some(cleanup),
+    extension_data : Some(extension_data),
+};
+
+#[no_mangle]
+pub extern "C" fn lv2_descriptor(index : u32) -> *const LV2Descriptor {
+    if index == 0 { &DESCRIPTOR } else { ptr::null() }
+}
+
+struct Plugin {
+    engine : Engine,
+    midi_event : LV2Urid,
+    midi_in : *const LV2AtomSequence,
+    out_left : *mut f32,
+    out_right : *mut f32,
+    params : Vec<Param>,
+    controls : Vec<*const f32>,
+    /// control values as last applied, to pick out the ones that changed
+    applied : Vec<f32>,
+}
+
+impl Plugin {
+    /// apply the control ports that changed since the last block
+    unsafe fn read_controls(&mut self) {
+        for (idx, control) in self.controls.iter().enumerate() {
+            if let Some(&value) = control.as_ref() {
+                if value != self.applied[idx] {
+                    self.engine.set_param(self.params[idx], value);
+                    self.applied[idx] = value;
+                }
+            }
+        }
+    }
+
+    /// render `out` up to each MIDI event of the input sequence
+    unsafe fn render(&mut self, out : &mut [f32]) {
+        let mut done = 0;
+        if let Some(sequence) = self.midi_in.as_ref() {
+            let body = (sequence as *const LV2AtomSequence as *const u8).add(size_of::<LV2Atom>());
+            let end = body.add(sequence.atom.size as usize);
+            let mut event = body.add(size_of::<LV2AtomSequence>() - size_of::<LV2Atom>());
+
+            while event.add(size_of::<LV2AtomEvent>()) <= end {
+                let header = &*(event as *const LV2AtomEvent);
+                let data = event.add(size_of::<LV2AtomEvent>());
+                if header.body.type_ == self.midi_event {
+                    let time = (header.frames.max(0) as usize).clamp(done, out.len());
+                    self.engine.render(&mut out[done..time]);
+                    done = time;
+                    self.engine.midi(slice::from_raw_parts(data, header.body.size as usize));
+                }
+                // events are padded to 64 bits
+                let size = size_of::<LV2AtomEvent>() + header.body.size as usize;
+                event = event.add((size + 7) & !7);
+            }
+        }
+        self.engine.render(&mut out[done..]);
+    }
+}
+
+unsafe extern "C" fn instantiate(_descriptor : *const LV2Descriptor, sample_rate : f64,
+    _bundle_path : *const c_char, features : *const *const LV2Feature) -> LV2Handle {
+    // the MIDI event type is only known through the host's URID map
+    let mut map = None;
+    let mut feature = features;
+    while !feature.is_null() && !(*feature).is_null() {
+        let f = &**feature;
+        if !f.uri.is_null() && CStr::from_ptr(f.uri) == URID_MAP_URI {
+            map = (f.data as *const LV2UridMap).as_ref();
+        }
+        feature = feature.add(1);
+    }
+    let Some(map) = map else {
+        return ptr::null_mut();
+    };
+    let Some(map_uri) = map.map else {
+        return ptr::null_mut();
+    };
+
+    let mut engine = Engine::new(Voice::new());
+    engine.set_sample_rate(sample_rate.round() as u32);
+    let params : Vec<Param> = ports().collect();
+    let plugin = Box::new(Plugin {
+        midi_event : map_uri(map.handle, MIDI_EVENT_URI.as_ptr()),
+        midi_in : ptr::null(),
+        out_left : ptr::null_mut(),
+        out_right : ptr::null_mut(),
+        controls : vec![ptr::null(); params.len()],
+        applied : params.iter().map(|p| p.get(engine.patch())).collect(),
+        params,
+        engine,
+    });
+    Box::into_raw(plugin) as LV2Handle
+}
+
+unsafe extern "C" fn connect_port(instance : LV2Handle, port : u32, data : *mut c_void) {
+    let plugin = &mut *(instance as *mut Plugin);
+    match port {
+        PORT_MIDI_IN => plugin.midi_in = data as *const LV2AtomSequence,
+        PORT_OUT_LEFT => plugin.out_left = data as *mut f32,
+        PORT_OUT_RIGHT => plugin.out_right = data as *mut f32,
+        _ => {
+            if let Some(control) = plugin.controls.get_mut((port - PORT_PARAMS) as usize) {
+                *control = data as *const f32;
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn activate(instance : LV2Handle) {
+    (*(instance as *mut Plugin)).engine.reset();
+}
+
+unsafe extern "C" fn run(instance : LV2Handle, sample_count : u32) {
+    let plugin = &mut *(instance as *mut Plugin);
+    if plugin.out_left.is_null() {
+        return;
+    }
+    let out = slice::from_raw_parts_mut(plugin.out_left, sample_count as usize);
+    plugin.read_controls();
+    plugin.render(out);
+    if !plugin.out_right.is_null() {
+        ptr::copy_nonoverlapping(plugin.out_left, plugin.out_right, sample_count as usize);
+    }
+}
+
+unsafe extern "C" fn deactivate(_instance : LV2Handle) {}
+
+unsafe extern "C" fn cleanup(instance : LV2Handle) {
+    drop(Box::from_raw(instance as *mut Plugin));
+}
+
+unsafe extern "C" fn extension_data(_uri : *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+/// the parameters with a control port
+pub fn ports() -> impl Iterator<Item = Param> {
+    Param::all().filter(|param| *param != Param::Algorithm)
+}
+
+/// port symbol of `param`: "op2.tune" becomes "op2_tune"
+pub fn symbol(param : Param) -> String {
+    param.name().replace('.', "_")
+}
+
+/// the ports a preset of an algorithm sets
+fn is_routing(param : Param) -> bool {
+    matches!(param, Param::Mod(..) | Param::Feedback(..) | Param::Op(_, OpParam::Output))
+}
+
+fn preset_uri(algorithm : usize) -> String {
+    format!("{PLUGIN_URI}#algorithm{}", algorithm + 1)
+}
+
+/// `manifest.ttl` of a bundle with the library at `binary` and the presets
+pub fn manifest_ttl(binary : &str) -> String {
+    let mut ttl = format!("@prefix lv2:  <http://lv2plug.in/ns/lv2core#> .\n\
+             @prefix pset: <http://lv2plug.in/ns/ext/presets#> .\n\
+             @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+             \n\
+             <{PLUGIN_URI}>\n\
+             \ta lv2:Plugin ;\n\
+             \tlv2:binary <{binary}> ;\n\
+             \trdfs:seeAlso <beriq_fm.ttl> .\n");
+    for algorithm in 0..ALGORITHM_COUNT {
+        let _ = write!(ttl, "\n<{}>\n\
+            \ta pset:Preset ;\n\
+            \tlv2:appliesTo <{PLUGIN_URI}> ;\n\
+            \trdfs:seeAlso <presets.ttl> .\n", preset_uri(algorithm));
+    }
+    ttl
+}
+
+/// `presets.ttl`, the routing of each algorithm
+pub fn presets_ttl() -> String {
+    let mut ttl = String::from(
+        "@prefix lv2:  <http://lv2plug.in/ns/lv2core#> .\n\
+         @prefix pset: <http://lv2plug.in/ns/ext/presets#> .\n\
+         @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n");
+    for algorithm in 0..ALGORITHM_COUNT {
+        let mut voice = Voice::new();
+        voice.set_algorithm(algorithm);
+        let _ = write!(ttl, "\n<{}>\n\
+            \ta pset:Preset ;\n\
+            \tlv2:appliesTo <{PLUGIN_URI}> ;\n\
+            \trdfs:label \"Algorithm {}\" ;\n\
+            \tlv2:port", preset_uri(algorithm), algorithm + 1);
+        let ports : Vec<String> = ports().filter(|param| is_routing(*param))
+            .map(|param| format!(" [ lv2:symbol \"{}\" ; pset:value {:?} ]", symbol(param), param.get(&voice)))
+            .collect();
+        ttl.push_str(&ports.join(" ,\n\t\t"));
+        ttl.push_str(" .\n");
+    }
+    ttl
+}
+
+/// `beriq_fm.ttl`, the ports of the plugin
+pub fn plugin_ttl() -> String {
+    let mut ttl = String::from(
+        "@prefix atom:  <http://lv2plug.in/ns/ext/atom#> .\n\
+         @prefix doap:  <http://usefulinc.com/ns/doap#> .\n\
+         @prefix lv2:   <http://lv2plug.in/ns/lv2core#> .\n\
+         @prefix midi:  <http://lv2plug.in/ns/ext/midi#> .\n\
+         @prefix rdf:   <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .\n\
+         @prefix rdfs:  <http://www.w3.org/2000/01/rdf-schema#> .\n\
+         @prefix units: <http://lv2plug.in/ns/extensions/units#> .\n\
+         @prefix urid:  <http://lv2plug.in/ns/ext/urid#> .\n\n");
+
+    let _ = write!(ttl, "<{PLUGIN_URI}>\n\
+        \ta lv2:Plugin, lv2:InstrumentPlugin ;\n\
+        \tdoap:name \"beriq FM\" ;\n\
+        \tlv2:requiredFeature urid:map ;\n\
+        \tlv2:optionalFeature lv2:hardRTCapable ;\n\
+        \tlv2:port [\n\
+        \t\ta lv2:InputPort, atom:AtomPort ;\n\
+        \t\tatom:bufferType atom:Sequence ;\n\
+        \t\tatom:supports midi:MidiEvent ;\n\
+        \t\tlv2:designation lv2:control ;\n\
+        \t\tlv2:index {PORT_MIDI_IN} ;\n\
+        \t\tlv2:symbol \"midi_in\" ;\n\
+        \t\tlv2:name \"MIDI in\"\n\
+        \t] , [\n\
+        \t\ta lv2:OutputPort, lv2:AudioPort ;\n\
+        \t\tlv2:index {PORT_OUT_LEFT} ;\n\
+        \t\tlv2:symbol \"out_left\" ;\n\
+        \t\tlv2:name \"Left\"\n\
+        \t] , [\n\
+        \t\ta lv2:OutputPort, lv2:AudioPort ;\n\
+        \t\tlv2:index {PORT_OUT_RIGHT} ;\n\
+        \t\tlv2:symbol \"out_right\" ;\n\
+        \t\tlv2:name \"Right\"\n\
+        \t]");
+
+    for (idx, param) in ports().enumerate() {
+        let info = param.info();
+        let name = param.display_name();
+        let _ = write!(ttl, " , [\n\
+            \t\ta lv2:InputPort, lv2:ControlPort ;\n\
+            \t\tlv2:index {} ;\n\
+            \t\tlv2:symbol \"{}\" ;\n\
+            \t\tlv2:name \"{name}\" ;\n\
+            \t\tlv2:default {:?} ;\n\
+            \t\tlv2:minimum {:?} ;\n\
+            \t\tlv2:maximum {:?}",
+            PORT_PARAMS as usize + idx, symbol(param), info.default, info.min, info.max);
+
+        if info.stepped {
+            ttl.push_str(" ;\n\t\tlv2:portProperty lv2:integer");
+        }
+        match info.unit {
+            Unit::Seconds => ttl.push_str(" ;\n\t\tunits:unit units:s"),
+            Unit::Semitones => ttl.push_str(" ;\n\t\tunits:unit units:semitone12TET"),
+            Unit::Octaves => ttl.push_str(" ;\n\t\tunits:unit units:oct"),
+            Unit::Choice(names) => {
+                ttl.push_str(" ;\n\t\tlv2:portProperty lv2:enumeration");
+                for (value, label) in names.iter().enumerate() {
+                    let _ = write!(ttl, " ;\n\t\tlv2:scalePoint [ rdfs:label \"{label}\" ; rdf:value {value}.0 ]");
+                }
+            }
+            _ => (),
+        }
+        ttl.push_str("\n\t]");
+    }
+    ttl.push_str(" .\n");
+    ttl
+}
+
+/// write the TTL files of a bundle for the library `binary` into `dir`
+pub fn write_bundle<P : AsRef<Path>>(dir : P, binary : &str) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("manifest.ttl"), manifest_ttl(binary))?;
+    fs::write(dir.join("presets.ttl"), presets_ttl())?;
+    fs::write(dir.join("beriq_fm.ttl"), plugin_ttl())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIDI_EVENT : LV2Urid = 7;
+
+    unsafe extern "C" fn map(_handle : *mut c_void, uri : *const c_char) -> LV2Urid {
+        if CStr::from_ptr(uri) == MIDI_EVENT_URI { MIDI_EVENT } else { 1 }
+    }
+
+    /// a sequence of 3-byte MIDI events at the given frames
+    fn sequence(events : &[(i64, [u8; 3])]) -> Vec<u64> {
+        let mut words = vec![0u64; 2 + 3 * events.len()];
+        let bytes = words.as_mut_ptr() as *mut u8;
+        unsafe {
+            let size = 8 + 24 * events.len();
+            *(bytes as *mut LV2AtomSequence) = LV2AtomSequence {
+                atom : LV2Atom { size : size as u32, type_ : 2 }, unit : 0, pad : 0 };
+            for (idx, (frames, data)) in events.iter().enumerate() {
+                let event = bytes.add(16 + 24 * idx);
+                *(event as *mut LV2AtomEvent) = LV2AtomEvent {
+                    frames : *frames, body : LV2Atom { size : 3, type_ : MIDI_EVENT } };
+                ptr::copy_nonoverlapping(data.as_ptr(), event.add(16), 3);
+            }
+        }
+        words
+    }
+
+    #[test]
+    fn test_ttl() {
+        let ttl = plugin_ttl();
+        let ports = Param::all().count() - 1 + PORT_PARAMS as usize;
+        assert_eq!(ttl.matches("lv2:index").count(), ports);
+        assert!(ttl.contains(&format!("lv2:index {} ;\n\t\tlv2:symbol \"glide_mode\"", PORT_PARAMS)));
+        assert!(!ttl.contains("lv2:symbol \"algorithm\""));
+        assert!(ttl.contains("lv2:symbol \"op4_smoothing\""));
+        assert!(ttl.contains("lv2:symbol \"fb4_1\" ;\n\t\tlv2:name \"Feedback 4>1\""));
+        assert!(ttl.ends_with("\t] .\n"));
+
+        let manifest = manifest_ttl("libberiq_fm.so");
+        assert!(manifest.contains("lv2:binary <libberiq_fm.so>"));
+        assert_eq!(manifest.matches("a pset:Preset").count(), ALGORITHM_COUNT);
+
+        // each preset sets the whole routing, here of algorithm 8
+        let presets = presets_ttl();
+        assert_eq!(presets.matches("a pset:Preset").count(), ALGORITHM_COUNT);
+        let last = &presets[presets.find("#algorithm8>").unwrap()..];
+        assert!(last.contains("rdfs:label \"Algorithm 8\""));
+        assert_eq!(last.matches("lv2:symbol").count(), 6 + 6 + 4);
+        assert!(last.contains("[ lv2:symbol \"mod1_2\" ; pset:value 0.0 ]"));
+        assert!(last.contains("[ lv2:symbol \"op3_output\" ; pset:value 1.0 ]"));
+        assert!(last.ends_with(" ] .\n"));
+    }
+
+    #[test]
+    fn test_run() {
+        const FRAMES : usize = 128;
+        let mut urid_map = LV2UridMap { handle : ptr::null_mut(), map : Some(map) };
+        let feature = LV2Feature { uri : URID_MAP_URI.as_ptr(), data : &mut urid_map as *mut LV2UridMap as *mut c_void };
+        let features = [&feature as *const LV2Feature, ptr::null()];
+
+        unsafe {
+            let descriptor = &*lv2_descriptor(0);
+            assert!(lv2_descriptor(1).is_null());
+            assert_eq!(CStr::from_ptr(descriptor.uri).to_str(), Ok(PLUGIN_URI));
+            let none = [ptr::null()];
+            assert!((descriptor.instantiate.unwrap())(descriptor, 44100.0, c"".as_ptr(), none.as_ptr()).is_null());
+
+            let instance = (descriptor.instantiate.unwrap())(descriptor, 44100.0, c"".as_ptr(), features.as_ptr());
+            let mut midi = sequence(&[(20, [0x90, 60, 100]), (90, [0x80, 60, 0])]);
+            let mut left = [1.0f32; FRAMES];
+            let mut right = [1.0f32; FRAMES];
+            let tune = ports().position(|p| p == Param::Op(1, OpParam::Tune)).unwrap() as u32;
+            let mut value = 0.5f32;
+
+            let connect = descriptor.connect_port.unwrap();
+            connect(instance, PORT_MIDI_IN, midi.as_mut_ptr() as *mut c_void);
+            connect(instance, PORT_OUT_LEFT, left.as_mut_ptr() as *mut c_void);
+            connect(instance, PORT_OUT_RIGHT, right.as_mut_ptr() as *mut c_void);
+            connect(instance, PORT_PARAMS + tune, &mut value as *mut f32 as *mut c_void);
+            (descriptor.activate.unwrap())(instance);
+            (descriptor.run.unwrap())(instance, FRAMES as u32);
+
+            assert!(left[..20].iter().all(|s| *s == 0.0));
+            assert!(left[20..].iter().any(|s| *s != 0.0));
+            assert_eq!(left, right);
+            let plugin = &*(instance as *const Plugin);
+            assert_eq!(Param::Op(1, OpParam::Tune).get(plugin.engine.patch()), 0.5);
+            assert_eq!(plugin.engine.pool.key(0), None);
+
+            (descriptor.cleanup.unwrap())(instance);
+        }
+    }
+}
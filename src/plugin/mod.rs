@@ -0,0 +1,115 @@
+//! plugin
+//!
+//! the engine as plugin formats see it: a voice pool playing one patch,
+//! rendered at the rate of the host and driven by notes, raw MIDI and
+//! parameter values. The format wrappers are behind cargo features.
+
+#[cfg(feature = "clap")]
+pub mod clap;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "lv2")]
+pub mod lv2;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "wasm")]
+pub mod web;
+
+use crate::midi::MidiEvent;
+use crate::synth::SAMPLE_FREQ;
+use crate::synth::morph::Morph;
+use crate::synth::param::Param;
+use crate::synth::resample::Resampler;
+use crate::synth::voice::Voice;
+use crate::synth::voice_pool::VoicePool;
+
+pub struct Engine {
+    pub pool : VoicePool,
+    patch : Voice,
+    resampler : Resampler,
+    morph_target : Option<Voice>,
+    morph : f32,
+}
+
+impl Engine {
+    pub fn new(patch : Voice) -> Engine {
+        Engine {
+            pool : VoicePool::new(patch),
+            patch,
+            resampler : Resampler::new(SAMPLE_FREQ),
+            morph_target : None,
+            morph : 0.0,
+        }
+    }
+
+    /// the patch as set through `set_patch` and `set_param`
+    pub fn patch(&self) -> &Voice {
+        &self.patch
+    }
+
+    /// play all voices with `patch`; sounding notes are cut
+    pub fn set_patch(&mut self, patch : Voice) {
+        self.patch = patch;
+        self.pool.set_patch(patch);
+        self.update_morph();
+    }
+
+    pub fn set_param(&mut self, param : Param, value : f32) {
+        param.set(&mut self.patch, value);
+        for voice in self.pool.voices_mut() {
+            param.set(voice, value);
+        }
+        self.update_morph();
+    }
+
+    /// morph from the patch towards `target`, or back to the patch alone
+    pub fn set_morph_target(&mut self, target : Option<Voice>) {
+        self.morph_target = target;
+        self.set_morph(self.morph);
+    }
+
+    /// move all voices, playing or not, to `amount` from 0 for the patch
+    /// to 1 for the morph target
+    pub fn set_morph(&mut self, amount : f32) {
+        self.morph = amount;
+        let target = self.morph_target.unwrap_or(self.patch);
+        Morph::new(self.patch, target).apply_pool(&mut self.pool, amount);
+    }
+
+    fn update_morph(&mut self) {
+        if self.morph_target.is_some() {
+            self.set_morph(self.morph);
+        }
+    }
+
+    /// rate of the output of `render`
+    pub fn set_sample_rate(&mut self, rate : u32) {
+        self.resampler = Resampler::new(rate);
+    }
+
+    /// play one raw MIDI message; program changes are ignored
+    pub fn midi(&mut self, bytes : &[u8]) {
+        if let Some(event) = MidiEvent::parse(bytes) {
+            event.apply(&mut self.pool);
+        }
+    }
+
+    /// stop all voices at once
+    pub fn reset(&mut self) {
+        self.pool.set_patch(self.patch);
+        self.update_morph();
+    }
+
+    pub fn render(&mut self, out : &mut [f32]) {
+        let pool = &mut self.pool;
+        for sample in out {
+            *sample = self.resampler.next(|| pool.get_sample());
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new(Voice::new())
+    }
+}
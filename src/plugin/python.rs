@@ -0,0 +1,259 @@
+//! python
+//!
+//! the `beriq_fm` Python extension: voices with their parameters by name
+//! or as a vector, the patch format and offline rendering to NumPy arrays.
+//! Build and install it into the active virtualenv with maturin:
+//!
+//! ```text
+//! maturin develop --release
+//! ```
+//!
+//! ```text
+//! import beriq_fm
+//! voice = beriq_fm.Voice.parse(open("bell.patch").read())
+//! voice["op2.tune"] = 1.5
+//! audio = beriq_fm.render_note(voice, key=57, hold=0.5, release=1.0)
+//! ```
+//!
+//! Rendering releases the GIL, so voices render in parallel from Python
+//! threads.
+
+use numpy::{PyArray1, PyArray2, PyArrayMethods};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::synth::SAMPLE_FREQ;
+use crate::synth::{algorithm, diagram};
+use crate::synth::param::Param;
+use crate::synth::patch::{self, PatchError};
+use crate::synth::random::{Constraints, Randomizer};
+use crate::synth::scheduler::{render_notes, Note};
+use crate::synth::voice::Voice;
+
+#[pyclass(name = "Voice", module = "beriq_fm")]
+pub struct PyVoice {
+    pub voice : Voice,
+}
+
+#[pymethods]
+impl PyVoice {
+    /// the default voice, as patch files start from
+    #[new]
+    fn new() -> PyVoice {
+        PyVoice { voice : Voice::new() }
+    }
+
+    /// a voice from the text of a patch file
+    #[staticmethod]
+    fn parse(text : &str) -> PyResult<PyVoice> {
+        Ok(PyVoice { voice : patch::parse(text).map_err(to_py_err)? })
+    }
+
+    #[staticmethod]
+    fn load(path : &str) -> PyResult<PyVoice> {
+        Ok(PyVoice { voice : patch::load(path).map_err(to_py_err)? })
+    }
+
+    fn save(&self, path : &str) -> PyResult<()> {
+        patch::save(&self.voice, path).map_err(to_py_err)
+    }
+
+    /// the voice in the text format of patch files
+    fn to_text(&self) -> String {
+        patch::to_text(&self.voice)
+    }
+
+    /// names of all parameters, in the order of `values`
+    #[staticmethod]
+    fn params() -> Vec<String> {
+        Param::all().map(Param::name).collect()
+    }
+
+    /// all parameter values, in the order of `params`
+    fn values<'py>(&self, py : Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_vec(py, Param::all().map(|param| param.get(&self.voice)).collect())
+    }
+
+    /// a voice from a vector of values as returned by `values`; values are
+    /// clamped to the range of their parameter
+    #[staticmethod]
+    fn from_values(values : Vec<f32>) -> PyResult<PyVoice> {
+        let count = Param::all().count();
+        if values.len() != count {
+            return Err(PyValueError::new_err(format!("expected {count} values, got {}", values.len())));
+        }
+        let mut voice = Voice::new();
+        for (param, value) in Param::all().zip(values) {
+            param.set(&mut voice, value);
+        }
+        Ok(PyVoice { voice })
+    }
+
+    /// a random playable voice drawn with `seed`; the parameters named in
+    /// `locked` keep their value in `base`
+    #[staticmethod]
+    #[pyo3(signature = (seed, base = None, locked = Vec::new()))]
+    fn random(seed : u64, base : Option<PyRef<PyVoice>>, locked : Vec<String>) -> PyResult<PyVoice> {
+        let constraints = Constraints {
+            locked : locked.iter().map(|name| param(name)).collect::<PyResult<_>>()?,
+            ..Constraints::default()
+        };
+        let base = base.map_or_else(Voice::new, |base| base.voice);
+        Ok(PyVoice { voice : Randomizer::with_constraints(seed, constraints).voice(&base) })
+    }
+
+    /// the routing in algorithm notation, such as "(1+2)>3>4"
+    #[getter]
+    fn get_routing(&self) -> PyResult<String> {
+        algorithm::to_text(&self.voice.routing).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[setter]
+    fn set_routing(&mut self, text : &str) -> PyResult<()> {
+        self.voice.routing = algorithm::parse(text).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(())
+    }
+
+    /// the routing drawn as "ascii", "dot" or "svg"
+    #[pyo3(signature = (format = "ascii"))]
+    fn diagram(&self, format : &str) -> PyResult<String> {
+        match format {
+            "ascii" => Ok(diagram::ascii(&self.voice)),
+            "dot" => Ok(diagram::dot(&self.voice)),
+            "svg" => Ok(diagram::svg(&self.voice)),
+            _ => Err(PyValueError::new_err(format!("unknown diagram format '{format}'"))),
+        }
+    }
+
+    /// `(min, max)` of a parameter
+    #[staticmethod]
+    fn range(name : &str) -> PyResult<(f32, f32)> {
+        Ok(param(name)?.range())
+    }
+
+    fn __getitem__(&self, name : &str) -> PyResult<f32> {
+        Ok(param(name)?.get(&self.voice))
+    }
+
+    fn __setitem__(&mut self, name : &str, value : f32) -> PyResult<()> {
+        param(name)?.set(&mut self.voice, value);
+        Ok(())
+    }
+
+    fn copy(&self) -> PyVoice {
+        PyVoice { voice : self.voice }
+    }
+
+    fn __eq__(&self, other : PyRef<PyVoice>) -> bool {
+        patch::to_text(&self.voice) == patch::to_text(&other.voice)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Voice(algorithm={})", Param::Algorithm.display(Param::Algorithm.get(&self.voice)))
+    }
+}
+
+/// render `notes`, each `(start, duration, key, velocity)` with times in
+/// seconds, for `length` seconds
+#[pyfunction]
+#[pyo3(signature = (voice, notes, length, sample_rate = SAMPLE_FREQ))]
+fn render<'py>(py : Python<'py>, voice : PyRef<PyVoice>, notes : Vec<Note>, length : f64,
+    sample_rate : u32) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    check_rate(sample_rate)?;
+    let voice = voice.voice;
+    let samples = py.detach(|| render_notes(voice, &notes, length, sample_rate));
+    Ok(PyArray1::from_vec(py, samples))
+}
+
+/// one note held for `hold` seconds and then released for `release`
+#[pyfunction]
+#[pyo3(signature = (voice, key = 60, velocity = 100, hold = 1.0, release = 0.5, sample_rate = SAMPLE_FREQ))]
+fn render_note<'py>(py : Python<'py>, voice : PyRef<PyVoice>, key : u8, velocity : u8, hold : f64,
+    release : f64, sample_rate : u32) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    check_rate(sample_rate)?;
+    let voice = voice.voice;
+    let samples = py.detach(|| render_notes(voice, &[(0.0, hold, key, velocity)], hold + release, sample_rate));
+    Ok(PyArray1::from_vec(py, samples))
+}
+
+/// `render_note` for each of `voices`, as one row per voice
+#[pyfunction]
+#[pyo3(signature = (voices, key = 60, velocity = 100, hold = 1.0, release = 0.5, sample_rate = SAMPLE_FREQ))]
+fn render_batch<'py>(py : Python<'py>, voices : Vec<PyRef<PyVoice>>, key : u8, velocity : u8, hold : f64,
+    release : f64, sample_rate : u32) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    check_rate(sample_rate)?;
+    let voices : Vec<Voice> = voices.iter().map(|voice| voice.voice).collect();
+    let rows = voices.len();
+    let samples = py.detach(|| {
+        voices.into_iter()
+            .flat_map(|voice| render_notes(voice, &[(0.0, hold, key, velocity)], hold + release, sample_rate))
+            .collect::<Vec<f32>>()
+    });
+    let columns = samples.len().checked_div(rows).unwrap_or(0);
+    PyArray1::from_vec(py, samples).reshape([rows, columns])
+}
+
+#[pymodule]
+fn beriq_fm(module : &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyVoice>()?;
+    module.add_function(wrap_pyfunction!(render, module)?)?;
+    module.add_function(wrap_pyfunction!(render_note, module)?)?;
+    module.add_function(wrap_pyfunction!(render_batch, module)?)?;
+    module.add("SAMPLE_FREQ", SAMPLE_FREQ)?;
+    Ok(())
+}
+
+fn param(name : &str) -> PyResult<Param> {
+    Param::from_name(name).ok_or_else(|| PyKeyError::new_err(name.to_string()))
+}
+
+fn check_rate(sample_rate : u32) -> PyResult<()> {
+    match sample_rate {
+        0 => Err(PyValueError::new_err("sample rate must be positive")),
+        _ => Ok(()),
+    }
+}
+
+fn to_py_err(error : PatchError) -> PyErr {
+    match error {
+        PatchError::Io(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::param::OpParam;
+
+    #[test]
+    fn test_voice() {
+        Python::initialize();
+        Python::attach(|py| {
+            let mut voice = PyVoice::parse("algorithm = 3\nop2.tune = 1.5\n").unwrap();
+            assert_eq!(voice.__getitem__("op2.tune").unwrap(), 1.5);
+            voice.__setitem__("op4.total_level", 12.0).unwrap();
+            assert_eq!(voice.voice.operators[3].total_level, 12);
+            assert!(voice.__getitem__("op5.tune").is_err());
+            assert!(PyVoice::parse("op1.tune = x").is_err());
+
+            let names = PyVoice::params();
+            assert_eq!(names.len(), Param::all().count());
+            assert_eq!(names[0], "algorithm");
+            assert!(names.contains(&Param::Op(1, OpParam::Tune).name()));
+            assert!(PyVoice::from_values(vec![0.0; 3]).is_err());
+            assert_eq!(voice.get_routing().unwrap(), "1+2>3>4");
+            voice.set_routing("1>(2,3,4)").unwrap();
+            assert_eq!(voice.__getitem__("algorithm").unwrap(), 5.0);
+            assert!(voice.set_routing("1>2>1").is_err());
+            assert!(voice.diagram("ascii").unwrap().starts_with("[1]-+++\n"));
+            assert!(voice.diagram("png").is_err());
+            assert_eq!(PyVoice::range("op1.total_level").unwrap(), (0.0, 255.0));
+
+            let base = Py::new(py, voice).unwrap();
+            let random = PyVoice::random(5, Some(base.borrow(py)), vec!["op2.tune".into()]).unwrap();
+            assert_eq!(random.__getitem__("op2.tune").unwrap(), 1.5);
+            assert!(PyVoice::random(5, None, vec!["op2.volume".into()]).is_err());
+        });
+    }
+}
@@ -0,0 +1,190 @@
+//! web
+//!
+//! the engine for the browser, to be driven from an AudioWorklet: the
+//! worklet owns a `Synth`, forwards notes and patches from its message port
+//! and fills each output block with `render`. Nothing here allocates per
+//! block.
+//!
+//! ```text
+//! cargo build --release --target wasm32-unknown-unknown -p beriq_fm_plugins --features wasm
+//! wasm-pack test --node -- --no-default-features --features wasm
+//! ```
+//!
+//! All DSP is integer `FP` arithmetic followed by IEEE float steps without
+//! fused operations, and tuning tables are quantized to `FP` when built, so
+//! a browser renders the very same samples as a native build; the tests
+//! check a rendering against a checksum taken natively.
+
+use wasm_bindgen::prelude::*;
+
+use crate::synth::diagram;
+use crate::synth::param::Param;
+use crate::synth::patch;
+use crate::synth::voice::Voice;
+use super::Engine;
+
+#[wasm_bindgen]
+pub struct Synth {
+    engine : Engine,
+}
+
+#[wasm_bindgen]
+impl Synth {
+    /// a synth rendering at `sample_rate`, usually the `sampleRate` global
+    /// of the worklet scope
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate : f32) -> Synth {
+        let mut engine = Engine::new(Voice::new());
+        engine.set_sample_rate(sample_rate.round() as u32);
+        Synth { engine }
+    }
+
+    /// load a patch in the text format of patch files; sounding notes are cut
+    pub fn load_patch(&mut self, text : &str) -> Result<(), String> {
+        let patch = patch::parse(text).map_err(|e| e.to_string())?;
+        self.engine.set_patch(patch);
+        Ok(())
+    }
+
+    /// load a patch to morph towards with `set_morph`
+    pub fn load_morph_target(&mut self, text : &str) -> Result<(), String> {
+        let target = patch::parse(text).map_err(|e| e.to_string())?;
+        self.engine.set_morph_target(Some(target));
+        Ok(())
+    }
+
+    /// morph amount from 0 for the patch to 1 for the morph target; notes
+    /// keep playing
+    pub fn set_morph(&mut self, amount : f32) {
+        self.engine.set_morph(amount);
+    }
+
+    /// the current patch as text
+    pub fn patch(&self) -> String {
+        patch::to_text(self.engine.patch())
+    }
+
+    /// the routing of the current patch as an SVG image
+    pub fn diagram(&self) -> String {
+        diagram::svg(self.engine.patch())
+    }
+
+    /// set a parameter by name as in patch files, e.g. "op2.tune"
+    pub fn set_param(&mut self, name : &str, value : f32) -> bool {
+        match Param::from_name(name) {
+            Some(param) => {
+                self.engine.set_param(param, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn note_on(&mut self, key : u8, velocity : u8) {
+        self.engine.pool.note_on(key, velocity);
+    }
+
+    pub fn note_off(&mut self, key : u8) {
+        self.engine.pool.note_off(key);
+    }
+
+    pub fn all_notes_off(&mut self) {
+        self.engine.pool.all_notes_off();
+    }
+
+    /// one raw MIDI message, as from Web MIDI
+    pub fn midi(&mut self, bytes : &[u8]) {
+        self.engine.midi(bytes);
+    }
+
+    /// fill `out`, typically the 128 frames of one worklet output channel
+    pub fn render(&mut self, out : &mut [f32]) {
+        self.engine.render(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    const BLOCK : usize = 128;
+
+    const PATCH : &str = "algorithm = 4\n\
+                          op1.waveform = 1\n\
+                          op2.total_level = 40\n\
+                          op2.tune = 1.5\n\
+                          op3.feedback_level = 90\n\
+                          op3.attack_rate = 0.02\n";
+
+    /// FNV-1a over the bits of every sample of a short phrase
+    fn checksum(sample_rate : f32) -> u64 {
+        let mut synth = Synth::new(sample_rate);
+        synth.load_patch(PATCH).unwrap();
+        let mut out = [0.0; BLOCK];
+        let mut hash : u64 = 0xcbf2_9ce4_8422_2325;
+        let mut sounding = false;
+        for block in 0..200 {
+            match block {
+                0 => synth.note_on(57, 127),
+                30 => synth.midi(&[0x90, 64, 90]),
+                60 => assert!(synth.set_param("op2.total_level", 10.0)),
+                90 => synth.midi(&[0xE0, 0x00, 0x60]),
+                120 => synth.note_off(57),
+                150 => synth.all_notes_off(),
+                _ => (),
+            }
+            synth.render(&mut out);
+            for sample in out {
+                sounding |= sample != 0.0;
+                for byte in sample.to_bits().to_le_bytes() {
+                    hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+                }
+            }
+        }
+        assert!(sounding);
+        hash
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(48000.0), 0x36eec2ca6ce8099f);
+        assert_eq!(checksum(44100.0), 0xf374875a7ef55f41);
+    }
+
+    /// Scala steps and the reference frequency go through f64 logarithms;
+    /// the table they make must be the same on every target
+    #[test]
+    fn test_tuning() {
+        use crate::synth::tuning::{KeyboardMap, Scale, Tuning};
+        let scale = Scale::parse("! test.scl\nTest\n 3\n 386.31371\n 3/2\n 2\n").unwrap();
+        let tuning = Tuning::new(&scale, &KeyboardMap::linear(60, 69, 432.0)).unwrap();
+        let mut hash : u64 = 0xcbf2_9ce4_8422_2325;
+        for key in 0..128 {
+            let repr = tuning.flog2(key).map_or(-1, |flog2| flog2.repr);
+            for byte in repr.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+        assert_eq!(hash, 0x1ce3d81db9147cf9);
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut synth = Synth::new(48000.0);
+        assert!(synth.load_patch("op9.tune = 1").is_err());
+        synth.load_patch(PATCH).unwrap();
+        assert!(synth.patch().contains("algorithm = 4"));
+        assert!(synth.diagram().contains(">fb 90</text>"));
+        assert!(!synth.set_param("op2.volume", 1.0));
+
+        synth.load_morph_target("algorithm = 6\nop2.total_level = 200").unwrap();
+        synth.set_morph(0.5);
+        assert!(synth.engine.pool.voices().all(|voice| voice.algorithm().is_none()));
+        assert!(synth.engine.pool.voices().all(|voice| voice.routing.output[0] == crate::fp::FP::from(0.5)));
+        assert!(synth.engine.pool.voices().all(|voice| voice.operators[1].total_level == 120));
+        assert!(synth.patch().contains("algorithm = 4"));
+        assert!(synth.load_morph_target("x").is_err());
+    }
+}
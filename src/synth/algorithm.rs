@@ -0,0 +1,331 @@
+//! algorithm
+//!
+//! text notation for the routing of a voice, as drawn above `ALGORITHMS`:
+//! operators are numbered from 1, `a>b` means the output of `a` modulates
+//! `b`, `+` and `,` put operators side by side and parentheses group them.
+//! The operators whose outputs end the expression are the carriers:
+//!
+//! ```text
+//! 1>2>3>4      a stack heard through op4
+//! (1+2)>3>4    op1 and op2 both modulate op3
+//! 1>(2,3,4)    op1 modulates three carriers
+//! 1+2>3+4      op2 modulates op3; op1, op3 and op4 are heard
+//! ```
+//!
+//! `>` binds tighter than `+` and `,`, which mean the same. An operator may
+//! appear more than once; its connections add up. Depths and levels are
+//! not part of the notation: parsing gives full ones, printing shows every
+//! connection whatever its depth. Neither are feedback paths, which parsing
+//! leaves out and printing ignores.
+
+use std::fmt;
+
+use crate::fp::*;
+
+use super::voice::Routing;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlgorithmError {
+    /// column (from 1) at which the text stops making sense, what was expected
+    Syntax(usize, &'static str),
+    /// an operator number that the voice does not have
+    UnknownOperator(usize),
+    /// operators, from 0, around a loop of modulation, first one repeated
+    Cycle(Vec<usize>),
+    /// modulation of an operator by a later one, which is computed after it
+    Backward(usize, usize),
+    /// operators, from 0, that are neither heard nor modulate one that is
+    Unreachable(Vec<usize>),
+    NoCarrier,
+}
+
+impl fmt::Display for AlgorithmError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let numbers = |ops : &[usize], separator : &str| ops.iter()
+            .map(|op| (op + 1).to_string()).collect::<Vec<_>>().join(separator);
+        match self {
+            AlgorithmError::Syntax(column, expected) => write!(f, "column {column}: expected {expected}"),
+            AlgorithmError::UnknownOperator(op)       => write!(f, "no operator {op}"),
+            AlgorithmError::Cycle(ops)                => write!(f, "modulation cycle {}", numbers(ops, ">")),
+            AlgorithmError::Backward(src, dst)        =>
+                write!(f, "operator {} cannot modulate operator {}, which comes before it", src + 1, dst + 1),
+            AlgorithmError::Unreachable(ops)          => write!(f, "operators not heard: {}", numbers(ops, ", ")),
+            AlgorithmError::NoCarrier                 => write!(f, "no carrier"),
+        }
+    }
+}
+
+impl std::error::Error for AlgorithmError {}
+
+/// the routing written as `text`, with full depths and levels
+pub fn parse<const N : usize>(text : &str) -> Result<Routing<N>, AlgorithmError> {
+    let mut parser = Parser { text : text.as_bytes(), pos : 0, operators : N, edges : Vec::new() };
+    let ends = parser.list()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("'>', '+', ',' or the end"));
+    }
+
+    let mut connected = [[false; N]; N];
+    for &(src, dst) in &parser.edges {
+        connected[src][dst] = true;
+    }
+    if let Some(cycle) = find_cycle(&connected) {
+        return Err(AlgorithmError::Cycle(cycle));
+    }
+    if let Some(&(src, dst)) = parser.edges.iter().find(|(src, dst)| src > dst) {
+        return Err(AlgorithmError::Backward(src, dst));
+    }
+
+    let mut routing = Routing::new();
+    for (src, dst) in parser.edges {
+        routing.modulation[src][dst] = FP_ONE;
+    }
+    for op in ends.outputs {
+        routing.output[op] = FP_ONE;
+    }
+    validate(&routing)?;
+    Ok(routing)
+}
+
+/// the notation for `routing`, which must be valid
+pub fn to_text<const N : usize>(routing : &Routing<N>) -> Result<String, AlgorithmError> {
+    validate(routing)?;
+    let carriers : Vec<usize> = (0..N).filter(|op| routing.output[*op] != FP_ZERO).collect();
+    Ok(terms(routing, &carriers).join("+"))
+}
+
+/// every operator is heard, directly or through those it modulates
+pub fn validate<const N : usize>(routing : &Routing<N>) -> Result<(), AlgorithmError> {
+    let mut heard = routing.carriers();
+    if !heard.contains(&true) {
+        return Err(AlgorithmError::NoCarrier);
+    }
+    for src in (0..N).rev() {
+        heard[src] |= (src + 1..N).any(|dst| heard[dst] && routing.modulation[src][dst] != FP_ZERO);
+    }
+    let unheard : Vec<usize> = (0..N).filter(|op| !heard[*op]).collect();
+    if !unheard.is_empty() {
+        return Err(AlgorithmError::Unreachable(unheard));
+    }
+    Ok(())
+}
+
+/// operators modulated from before a part of an expression, and those
+/// whose outputs leave it
+struct Ends {
+    inputs : Vec<usize>,
+    outputs : Vec<usize>,
+}
+
+struct Parser<'a> {
+    text : &'a [u8],
+    pos : usize,
+    /// number of operators of the voice
+    operators : usize,
+    /// (source, destination) of each `>`
+    edges : Vec<(usize, usize)>,
+}
+
+impl Parser<'_> {
+    /// chains side by side: `a+b`, `a,b`
+    fn list(&mut self) -> Result<Ends, AlgorithmError> {
+        let mut ends = self.chain()?;
+        while let Some(b'+' | b',') = self.peek() {
+            self.pos += 1;
+            let next = self.chain()?;
+            ends.inputs.extend(next.inputs);
+            ends.outputs.extend(next.outputs);
+        }
+        Ok(ends)
+    }
+
+    /// groups modulating each other in turn: `a>b>c`
+    fn chain(&mut self) -> Result<Ends, AlgorithmError> {
+        let first = self.group()?;
+        let mut outputs = first.outputs;
+        while let Some(b'>') = self.peek() {
+            self.pos += 1;
+            let next = self.group()?;
+            for &src in &outputs {
+                self.edges.extend(next.inputs.iter().map(|&dst| (src, dst)));
+            }
+            outputs = next.outputs;
+        }
+        Ok(Ends { inputs : first.inputs, outputs })
+    }
+
+    /// an operator number or a list in parentheses
+    fn group(&mut self) -> Result<Ends, AlgorithmError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let ends = self.list()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("')'"));
+                }
+                self.pos += 1;
+                Ok(ends)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.text.get(self.pos).is_some_and(u8::is_ascii_digit) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap()
+                    .parse::<usize>().unwrap_or(usize::MAX);
+                if !(1..=self.operators).contains(&number) {
+                    return Err(AlgorithmError::UnknownOperator(number));
+                }
+                Ok(Ends { inputs : vec![number - 1], outputs : vec![number - 1] })
+            }
+            _ => Err(self.error("an operator or '('")),
+        }
+    }
+
+    /// next character that is not white space
+    fn peek(&mut self) -> Option<u8> {
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.text.get(self.pos).copied()
+    }
+
+    fn error(&self, expected : &'static str) -> AlgorithmError {
+        AlgorithmError::Syntax(self.pos + 1, expected)
+    }
+}
+
+/// a path from an operator back to itself, if any
+fn find_cycle<const N : usize>(connected : &[[bool; N]; N]) -> Option<Vec<usize>> {
+    fn visit<const N : usize>(op : usize, connected : &[[bool; N]; N], path : &mut Vec<usize>,
+        done : &mut [bool; N]) -> Option<Vec<usize>> {
+        if let Some(start) = path.iter().position(|o| *o == op) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(op);
+            return Some(cycle);
+        }
+        if done[op] {
+            return None;
+        }
+        path.push(op);
+        for dst in 0..N {
+            if connected[op][dst] {
+                if let Some(cycle) = visit(dst, connected, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        done[op] = true;
+        None
+    }
+
+    let mut done = [false; N];
+    (0..N).find_map(|op| visit(op, connected, &mut Vec::new(), &mut done))
+}
+
+fn modulators<const N : usize>(routing : &Routing<N>, op : usize) -> Vec<usize> {
+    (0..op).filter(|src| routing.modulation[*src][op] != FP_ZERO).collect()
+}
+
+/// `ops` with what modulates them, as terms to put side by side; operators
+/// with the same modulators share them: `1>(2,3)`
+fn terms<const N : usize>(routing : &Routing<N>, ops : &[usize]) -> Vec<String> {
+    let mut list = Vec::new();
+    let mut done = Vec::new();
+    for &op in ops {
+        if done.contains(&op) {
+            continue;
+        }
+        let sources = modulators(routing, op);
+        if sources.is_empty() {
+            list.push((op + 1).to_string());
+            done.push(op);
+            continue;
+        }
+
+        let group : Vec<usize> = ops.iter().copied()
+            .filter(|o| modulators(routing, *o) == sources).collect();
+        let names : Vec<String> = group.iter().map(|o| (o + 1).to_string()).collect();
+        let names = match names.len() {
+            1 => names.join(","),
+            _ => format!("({})", names.join(",")),
+        };
+        let sources = match terms(routing, &sources)[..] {
+            [ref single] => single.clone(),
+            ref several => format!("({})", several.join("+")),
+        };
+        list.push(format!("{sources}>{names}"));
+        done.extend(group);
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::param::NUM_OPERATORS;
+    use crate::synth::voice::ALGORITHMS;
+
+    #[test]
+    fn test_presets() {
+        let expected = [
+            "1>2>3>4",
+            "(1+2)>3>4",
+            "(1+2>3)>4",
+            "1+2>3>4",
+            "1>2+3>4",
+            "1>(2,3,4)",
+            "1+2>3+4",
+            "1+2+3+4",
+        ];
+        for (routing, text) in ALGORITHMS.iter().zip(expected) {
+            assert_eq!(to_text(routing).unwrap(), text);
+            assert_eq!(parse(text).unwrap(), *routing, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" ( 1 , 2 ) > 3>4 ").unwrap(), ALGORITHMS[1]);
+        assert_eq!(parse("4+1+3+2").unwrap(), ALGORITHMS[7]);
+        assert_eq!(parse::<4>("1>(2+3>4)+3>4").unwrap(), parse("1>2+1>3>4").unwrap());
+
+        // not series-parallel: op1 modulates op2 and op3, op2 modulates op3
+        let routing : Routing<4> = parse("(1>2+1)>3>4").unwrap();
+        assert_eq!(to_text(&routing).unwrap(), "(1+1>2)>3>4");
+        assert_eq!(parse(&to_text(&routing).unwrap()).unwrap(), routing);
+
+        // any connection shows, whatever its depth
+        let mut shallow = ALGORITHMS[5];
+        shallow.modulation[0][2] = FP::from(0.25);
+        shallow.output[3] = FP::from(0.5);
+        assert_eq!(to_text(&shallow).unwrap(), "1>(2,3,4)");
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            ("", AlgorithmError::Syntax(1, "an operator or '('")),
+            ("1>>2", AlgorithmError::Syntax(3, "an operator or '('")),
+            ("(1+2>3>4", AlgorithmError::Syntax(9, "')'")),
+            ("1 2>3>4", AlgorithmError::Syntax(3, "'>', '+', ',' or the end")),
+            ("1>2>3>5", AlgorithmError::UnknownOperator(5)),
+            ("0>1", AlgorithmError::UnknownOperator(0)),
+            ("1>2>3>1+4", AlgorithmError::Cycle(vec![0, 1, 2, 0])),
+            ("2>2+1>3>4", AlgorithmError::Cycle(vec![1, 1])),
+            ("2>1>3>4", AlgorithmError::Backward(1, 0)),
+            ("1>2>4", AlgorithmError::Unreachable(vec![2])),
+        ];
+        for (text, error) in cases {
+            assert_eq!(parse::<4>(text), Err(error), "{text}");
+        }
+
+        let mut routing = ALGORITHMS[0];
+        routing.output = [FP_ZERO; NUM_OPERATORS];
+        assert_eq!(to_text(&routing), Err(AlgorithmError::NoCarrier));
+        routing.output[2] = FP_ONE;
+        assert_eq!(to_text(&routing), Err(AlgorithmError::Unreachable(vec![3])));
+        assert_eq!(AlgorithmError::Cycle(vec![0, 1, 0]).to_string(), "modulation cycle 1>2>1");
+    }
+}
@@ -0,0 +1,230 @@
+//! control
+//!
+//! plays a voice pool on the audio thread while other threads control it.
+//! A `ControlHandle` can be cloned and sent anywhere; its events travel to
+//! the audio thread over a bounded lock-free queue, so sending never blocks
+//! and neither side allocates after creation.
+//!
+//! The audio side picks up events once per block of `BLOCK_SIZE` samples.
+//! An event applies at the start of that block, or `offset` samples into it.
+use std::sync::Arc;
+#[cfg(feature = "rodio")]
+use std::time::Duration;
+use crossbeam_queue::ArrayQueue;
+#[cfg(feature = "rodio")]
+use rodio::source::Source;
+
+use super::param::Param;
+use super::voice_pool::VoicePool;
+
+pub const BLOCK_SIZE : u32 = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    NoteOn { key : u8, velocity : u8 },
+    NoteOff { key : u8 },
+    /// centered: -8192..8191
+    PitchBend { value : i32 },
+    ControlChange { cc : u8, value : u8 },
+    SetParam { param : Param, value : f32 },
+    SetAlgorithm { algorithm : usize },
+    AllNotesOff,
+}
+
+impl Event {
+    pub fn apply(&self, pool : &mut VoicePool) {
+        match *self {
+            Event::NoteOn { key, velocity } => pool.note_on(key, velocity),
+            Event::NoteOff { key } => pool.note_off(key),
+            Event::PitchBend { value } => pool.set_bend(value),
+            Event::ControlChange { cc, value } => pool.control_change(cc, value),
+            Event::SetParam { param, value } => {
+                for voice in pool.voices_mut() {
+                    param.set(voice, value);
+                }
+            }
+            Event::SetAlgorithm { algorithm } => {
+                for voice in pool.voices_mut() {
+                    Param::Algorithm.set(voice, algorithm as f32);
+                }
+            }
+            Event::AllNotesOff => pool.all_notes_off(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlockEvent {
+    /// samples into the block, 0 is the block boundary
+    pub offset : u32,
+    pub event : Event,
+}
+
+/// sends events to a `ControlledPool`
+#[derive(Debug, Clone)]
+pub struct ControlHandle {
+    queue : Arc<ArrayQueue<BlockEvent>>,
+}
+
+impl ControlHandle {
+    /// queue `event` for the start of the next block;
+    /// gives the event back if the queue is full
+    pub fn send(&self, event : Event) -> Result<(), Event> {
+        self.send_at(0, event)
+    }
+
+    /// queue `event` for `offset` samples into the next block
+    pub fn send_at(&self, offset : u32, event : Event) -> Result<(), Event> {
+        self.queue.push(BlockEvent { offset : offset.min(BLOCK_SIZE - 1), event })
+            .map_err(|block_event| block_event.event)
+    }
+
+    pub fn note_on(&self, key : u8, velocity : u8) -> Result<(), Event> {
+        self.send(Event::NoteOn { key, velocity })
+    }
+
+    pub fn note_off(&self, key : u8) -> Result<(), Event> {
+        self.send(Event::NoteOff { key })
+    }
+
+    pub fn set_param(&self, param : Param, value : f32) -> Result<(), Event> {
+        self.send(Event::SetParam { param, value })
+    }
+}
+
+/// the audio thread side: renders the pool, applying queued events
+pub struct ControlledPool {
+    pub pool : VoicePool,
+    queue : Arc<ArrayQueue<BlockEvent>>,
+    /// events of the current block, ordered by offset
+    block : Vec<BlockEvent>,
+    next : usize,
+    position : u32,
+}
+
+/// `capacity` is the number of events that can wait for the audio thread
+pub fn controlled(pool : VoicePool, capacity : usize) -> (ControlHandle, ControlledPool) {
+    let queue = Arc::new(ArrayQueue::new(capacity));
+    let handle = ControlHandle { queue : queue.clone() };
+    let controlled = ControlledPool {
+        pool,
+        queue,
+        block : Vec::with_capacity(capacity),
+        next : 0,
+        position : 0,
+    };
+    (handle, controlled)
+}
+
+impl ControlledPool {
+    pub fn get_sample(&mut self) -> f32 {
+        if self.position == 0 {
+            self.start_block();
+        }
+        while let Some(block_event) = self.block.get(self.next) {
+            if block_event.offset > self.position {
+                break;
+            }
+            block_event.event.apply(&mut self.pool);
+            self.next += 1;
+        }
+
+        self.position = (self.position + 1) % BLOCK_SIZE;
+        self.pool.get_sample()
+    }
+
+    fn start_block(&mut self) {
+        self.block.clear();
+        self.next = 0;
+        while self.block.len() < self.block.capacity() {
+            match self.queue.pop() {
+                Some(block_event) => self.block.push(block_event),
+                None => break,
+            }
+        }
+
+        // stable insertion sort, events at the same offset keep their order
+        for i in 1..self.block.len() {
+            let mut j = i;
+            while j > 0 && self.block[j - 1].offset > self.block[j].offset {
+                self.block.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+}
+
+impl Iterator for ControlledPool {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        return Some(self.get_sample());
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Source for ControlledPool {
+    fn channels(&self) -> u16 {
+        return 1;
+    }
+
+     fn sample_rate(&self) -> u32 {
+        return super::SAMPLE_FREQ;
+     }
+
+     fn current_frame_len(&self) -> Option<usize> {
+        return None;
+     }
+
+     fn total_duration(&self) -> Option<Duration> {
+        return None;
+     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::synth::voice::Voice;
+    use crate::synth::param::OpParam;
+
+    #[test]
+    fn test_control() {
+        let (handle, mut audio) = controlled(VoicePool::new(Voice::new()), 8);
+        audio.get_sample();
+
+        let other = handle.clone();
+        thread::spawn(move || {
+            other.send_at(10, Event::NoteOff { key : 60 }).unwrap();
+            other.send_at(5, Event::NoteOn { key : 60, velocity : 100 }).unwrap();
+        }).join().unwrap();
+        handle.set_param(Param::Op(2, OpParam::TotalLevel), 12.0).unwrap();
+
+        // events wait for the next block
+        for _ in 1..BLOCK_SIZE {
+            audio.get_sample();
+        }
+        assert_eq!(audio.pool.key(0), None);
+
+        // then apply at their offset, in offset order
+        for _ in 0..5 {
+            audio.get_sample();
+        }
+        assert_eq!(audio.pool.key(0), None);
+        assert!(audio.pool.voices().all(|voice| voice.operators[2].total_level == 12));
+        audio.get_sample();
+        assert_eq!(audio.pool.key(0), Some(60));
+        for _ in 6..10 {
+            audio.get_sample();
+        }
+        assert_eq!(audio.pool.key(0), Some(60));
+        audio.get_sample();
+        assert_eq!(audio.pool.key(0), None);
+
+        // a full queue hands the event back
+        for key in 0..8 {
+            handle.note_on(key, 100).unwrap();
+        }
+        assert_eq!(handle.note_off(1), Err(Event::NoteOff { key : 1 }));
+    }
+}
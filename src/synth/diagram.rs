@@ -0,0 +1,393 @@
+//! diagram
+//!
+//! the routing of a voice drawn as ASCII art for the terminal, as Graphviz
+//! DOT for documentation and as SVG for the web. Operators stand in columns
+//! by how many modulators come before them and in rows by number, so every
+//! connection runs right and down; the carriers join on the right:
+//!
+//! ```text
+//! [1]-+++
+//!     |||
+//!     ||+-[2]-+
+//!     ||      |
+//!     |+--[3]-+
+//!     |       |
+//!     +---[4]-+->
+//! ```
+//!
+//! DOT and SVG label modulation depths and output levels below full, and
+//! draw the feedback of operators into themselves and the feedback paths
+//! back to earlier operators dashed; ASCII lists them under the drawing.
+
+use std::fmt::Write;
+
+use crate::fp::*;
+
+use super::voice::FmVoice;
+
+/// a line of the diagram, operators from 0
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Edge {
+    /// source, destination, depth
+    Modulation(usize, usize, f32),
+    /// operator, level
+    Output(usize, f32),
+    /// operator, level 0..255
+    Feedback(usize, u8),
+    /// source, earlier destination, depth
+    FeedbackPath(usize, usize, f32),
+}
+
+fn edges<const N : usize>(voice : &FmVoice<N>) -> Vec<Edge> {
+    let routing = &voice.routing;
+    let mut edges = Vec::new();
+    for src in 0..N {
+        for dst in src + 1..N {
+            if routing.modulation[src][dst] != FP_ZERO {
+                edges.push(Edge::Modulation(src, dst, routing.modulation[src][dst].to_f32()));
+            }
+        }
+    }
+    for (op, level) in routing.output.iter().enumerate() {
+        if *level != FP_ZERO {
+            edges.push(Edge::Output(op, level.to_f32()));
+        }
+    }
+    for (op, operator) in voice.operators.iter().enumerate() {
+        if operator.feedback_level != 0 {
+            edges.push(Edge::Feedback(op, operator.feedback_level));
+        }
+    }
+    for src in 0..N {
+        for dst in 0..src {
+            if routing.feedback[src][dst] != FP_ZERO {
+                edges.push(Edge::FeedbackPath(src, dst, routing.feedback[src][dst].to_f32()));
+            }
+        }
+    }
+    edges
+}
+
+/// column of each operator: one more than that of its last modulator
+fn ranks<const N : usize>(edges : &[Edge]) -> [usize; N] {
+    let mut ranks = [0; N];
+    for dst in 0..N {
+        for edge in edges {
+            if let Edge::Modulation(src, d, _) = *edge {
+                if d == dst {
+                    ranks[dst] = ranks[dst].max(ranks[src] + 1);
+                }
+            }
+        }
+    }
+    ranks
+}
+
+/// `value` as a label, empty at full scale
+fn percent(value : f32) -> String {
+    if value == 1.0 { String::new() } else { format!("{:.0}%", value * 100.0) }
+}
+
+struct Canvas {
+    rows : Vec<Vec<u8>>,
+}
+
+impl Canvas {
+    fn get(&self, x : usize, y : usize) -> u8 {
+        self.rows.get(y).and_then(|row| row.get(x)).copied().unwrap_or(b' ')
+    }
+
+    fn put(&mut self, x : usize, y : usize, c : u8) {
+        if self.rows.len() <= y {
+            self.rows.resize(y + 1, Vec::new());
+        }
+        let row = &mut self.rows[y];
+        if row.len() <= x {
+            row.resize(x + 1, b' ');
+        }
+        row[x] = c;
+    }
+
+    /// lines crossing a vertical one pass under it
+    fn hline(&mut self, y : usize, x0 : usize, x1 : usize) {
+        for x in x0..=x1 {
+            if self.get(x, y) == b' ' {
+                self.put(x, y, b'-');
+            }
+        }
+    }
+
+    fn vline(&mut self, x : usize, y0 : usize, y1 : usize) {
+        for y in y0..=y1 {
+            if matches!(self.get(x, y), b' ' | b'-') {
+                self.put(x, y, b'|');
+            }
+        }
+    }
+
+    fn text(&mut self, x : usize, y : usize, text : &str) {
+        for (i, c) in text.bytes().enumerate() {
+            self.put(x + i, y, c);
+        }
+    }
+
+    fn to_text(&self) -> String {
+        self.rows.iter()
+            .map(|row| String::from_utf8_lossy(row).trim_end().to_string() + "\n")
+            .collect()
+    }
+}
+
+/// the routing as ASCII art, followed by the depths, levels and feedback
+/// that the drawing does not show
+pub fn ascii<const N : usize>(voice : &FmVoice<N>) -> String {
+    let edges = edges(voice);
+    let ranks = ranks::<N>(&edges);
+    let last = ranks.iter().copied().max().unwrap_or(0);
+
+    // each operator gets a column of its own to come down to its row in,
+    // in front of its rank, the lowest row first so that lines do not cross
+    let width = (1..=last).map(|r| ranks.iter().filter(|rank| **rank == r).count()).max().unwrap_or(0);
+    let step = width + 5;
+    let x = |op : usize| ranks[op] * step;
+    let y = |op : usize| 2 * op;
+    let mut gutter = [0; N];
+    for rank in 1..=last {
+        let ops : Vec<usize> = (0..N).rev().filter(|op| ranks[*op] == rank).collect();
+        for (k, op) in ops.into_iter().enumerate() {
+            gutter[op] = rank * step - width - 1 + k;
+        }
+    }
+
+    let mut canvas = Canvas { rows : Vec::new() };
+    let mut corners = Vec::new();
+    for edge in &edges {
+        if let Edge::Modulation(src, dst, _) = *edge {
+            let g = gutter[dst];
+            canvas.hline(y(src), x(src) + 3, g);
+            canvas.vline(g, y(src), y(dst));
+            canvas.hline(y(dst), g, x(dst) - 1);
+            corners.extend([(g, y(src)), (g, y(dst))]);
+        }
+    }
+
+    let carriers : Vec<usize> = edges.iter()
+        .filter_map(|edge| match *edge { Edge::Output(op, _) => Some(op), _ => None })
+        .collect();
+    if let (Some(&first), Some(&bottom)) = (carriers.first(), carriers.last()) {
+        let out = last * step + 4;
+        for &op in &carriers {
+            canvas.hline(y(op), x(op) + 3, out);
+        }
+        if carriers.len() > 1 {
+            canvas.vline(out, y(first), y(bottom));
+            corners.extend(carriers.iter().map(|op| (out, y(*op))));
+        }
+        canvas.text(out + 1, y(bottom), "->");
+    }
+
+    for (x, y) in corners {
+        canvas.put(x, y, b'+');
+    }
+    for op in 0..N {
+        canvas.text(x(op), y(op), &format!("[{}]", op + 1));
+    }
+
+    let mut text = canvas.to_text();
+    for edge in &edges {
+        let note = match *edge {
+            Edge::Modulation(src, dst, depth) if depth != 1.0 =>
+                format!("{}>{} depth {}\n", src + 1, dst + 1, percent(depth)),
+            Edge::Output(op, level) if level != 1.0 => format!("{} output {}\n", op + 1, percent(level)),
+            Edge::Feedback(op, level) => format!("{} feedback {level}\n", op + 1),
+            Edge::FeedbackPath(src, dst, depth) =>
+                format!("{}>{} feedback {:.0}%\n", src + 1, dst + 1, depth * 100.0),
+            _ => continue,
+        };
+        text.push_str(&note);
+    }
+    text
+}
+
+/// the routing as a Graphviz graph: `dot -Tpng`
+pub fn dot<const N : usize>(voice : &FmVoice<N>) -> String {
+    let mut text = String::from("digraph algorithm {\n    rankdir=LR;\n    node [shape=box];\n");
+    for op in 0..N {
+        let _ = writeln!(text, "    op{0} [label=\"{0}\"];", op + 1);
+    }
+    text.push_str("    out [shape=plaintext];\n");
+
+    let label = |value : String| match value.is_empty() {
+        true => String::new(),
+        false => format!(" [label=\"{value}\"]"),
+    };
+    for edge in edges(voice) {
+        let _ = match edge {
+            Edge::Modulation(src, dst, depth) =>
+                writeln!(text, "    op{} -> op{}{};", src + 1, dst + 1, label(percent(depth))),
+            Edge::Output(op, level) => writeln!(text, "    op{} -> out{};", op + 1, label(percent(level))),
+            Edge::Feedback(op, level) =>
+                writeln!(text, "    op{0} -> op{0} [label=\"fb {level}\", style=dashed];", op + 1),
+            // back against the rank order, which it must not change
+            Edge::FeedbackPath(src, dst, depth) => writeln!(text,
+                "    op{} -> op{} [label=\"fb {:.0}%\", style=dashed, constraint=false];",
+                src + 1, dst + 1, depth * 100.0),
+        };
+    }
+    text.push_str("}\n");
+    text
+}
+
+const SVG_COLUMN : usize = 90;
+const SVG_ROW : usize = 50;
+const SVG_BOX : (usize, usize) = (40, 30);
+const SVG_MARGIN : usize = 30;
+
+/// the routing as a standalone SVG image
+pub fn svg<const N : usize>(voice : &FmVoice<N>) -> String {
+    let edges = edges(voice);
+    let ranks = ranks::<N>(&edges);
+    let last = ranks.iter().copied().max().unwrap_or(0);
+
+    // left edge and middle height of each box
+    let x = |op : usize| SVG_MARGIN + ranks[op] * SVG_COLUMN;
+    let y = |op : usize| SVG_MARGIN + op * SVG_ROW + SVG_BOX.1 / 2;
+    let out = (SVG_MARGIN + (last + 1) * SVG_COLUMN, SVG_MARGIN + (N - 1) * SVG_ROW / 2 + SVG_BOX.1 / 2);
+    let (width, height) = (out.0 + SVG_MARGIN + 20, SVG_MARGIN * 2 + (N - 1) * SVG_ROW + SVG_BOX.1);
+
+    let mut text = String::new();
+    let _ = writeln!(text, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+        viewBox=\"0 0 {width} {height}\" font-family=\"sans-serif\" font-size=\"12\">");
+    text.push_str("<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+        markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M0,0 L10,5 L0,10 z\"/></marker></defs>\n");
+
+    let line = |(x0, y0) : (usize, usize), (x1, y1) : (usize, usize), label : String, text : &mut String| {
+        let bend = (x1 - x0) / 2;
+        let _ = writeln!(text, "<path d=\"M{x0},{y0} C{},{y0} {},{y1} {x1},{y1}\" fill=\"none\" stroke=\"black\" \
+            marker-end=\"url(#arrow)\"/>", x0 + bend, x1 - bend);
+        if !label.is_empty() {
+            let _ = writeln!(text, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{label}</text>",
+                (x0 + x1) / 2, (y0 + y1) / 2 - 4);
+        }
+    };
+    for edge in &edges {
+        match *edge {
+            Edge::Modulation(src, dst, depth) =>
+                line((x(src) + SVG_BOX.0, y(src)), (x(dst), y(dst)), percent(depth), &mut text),
+            Edge::Output(op, level) => line((x(op) + SVG_BOX.0, y(op)), out, percent(level), &mut text),
+            Edge::Feedback(op, level) => {
+                let (left, right, top) = (x(op) + 10, x(op) + SVG_BOX.0 - 10, y(op) - SVG_BOX.1 / 2);
+                let _ = writeln!(text, "<path d=\"M{right},{top} C{right},{0} {left},{0} {left},{top}\" \
+                    fill=\"none\" stroke=\"black\" stroke-dasharray=\"3,2\" marker-end=\"url(#arrow)\"/>",
+                    top - 18);
+                let _ = writeln!(text, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">fb {level}</text>",
+                    x(op) + SVG_BOX.0 / 2, top - 16);
+            }
+            // out of the left of the source and round into the destination
+            Edge::FeedbackPath(src, dst, depth) => {
+                let ((x0, y0), (x1, y1)) = ((x(src), y(src)), (x(dst), y(dst)));
+                let bend = SVG_MARGIN - 5;
+                let _ = writeln!(text, "<path d=\"M{x0},{y0} C{},{y0} {},{y1} {x1},{y1}\" fill=\"none\" \
+                    stroke=\"black\" stroke-dasharray=\"3,2\" marker-end=\"url(#arrow)\"/>", x0 - bend, x1 - bend);
+                let _ = writeln!(text, "<text x=\"{}\" y=\"{}\" text-anchor=\"start\">fb {:.0}%</text>",
+                    x0.min(x1) - bend + 4, (y0 + y1) / 2, depth * 100.0);
+            }
+        }
+    }
+
+    for op in 0..N {
+        let _ = writeln!(text, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"black\"/>",
+            x(op), y(op) - SVG_BOX.1 / 2, SVG_BOX.0, SVG_BOX.1);
+        let _ = writeln!(text, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            x(op) + SVG_BOX.0 / 2, y(op) + 4, op + 1);
+    }
+    let _ = writeln!(text, "<text x=\"{}\" y=\"{}\">out</text>", out.0 + 4, out.1 + 4);
+    text.push_str("</svg>\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::param::NUM_OPERATORS;
+    use crate::synth::voice::Voice;
+
+    fn voice(algorithm : usize) -> Voice {
+        let mut voice = Voice::new();
+        voice.set_algorithm(algorithm);
+        voice
+    }
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(ascii(&voice(5)), "\
+[1]-+++
+    |||
+    ||+-[2]-+
+    ||      |
+    |+--[3]-+
+    |       |
+    +---[4]-+->
+");
+        assert_eq!(ascii(&voice(1)), "\
+[1]-+
+    |
+[2]-+
+    |
+    +-[3]-+
+          |
+          +-[4]--->
+");
+
+        let mut custom = voice(7);
+        custom.routing.modulation[0][3] = FP::from(0.5);
+        custom.routing.output[0] = FP_ZERO;
+        custom.routing.output[2] = FP::from(0.25);
+        custom.operators[1].feedback_level = 40;
+        custom.routing.feedback[3][0] = FP::from(0.5);
+        assert_eq!(ascii(&custom), "\
+[1]-+
+    |
+[2]-|-----+
+    |     |
+[3]-|-----+
+    |     |
+    +-[4]-+->
+1>4 depth 50%
+3 output 25%
+2 feedback 40
+4>1 feedback 50%
+");
+    }
+
+    #[test]
+    fn test_dot() {
+        let mut voice = voice(4);
+        voice.routing.modulation[2][3] = FP::from(0.75);
+        voice.operators[0].feedback_level = 100;
+        voice.routing.feedback[3][1] = FP_ONE;
+        let text = dot(&voice);
+        assert!(text.starts_with("digraph algorithm {\n"));
+        for line in ["op1 -> op2;", "op3 -> op4 [label=\"75%\"];", "op2 -> out;", "op4 -> out;",
+            "op1 -> op1 [label=\"fb 100\", style=dashed];",
+            "op4 -> op2 [label=\"fb 100%\", style=dashed, constraint=false];"] {
+            assert!(text.contains(line), "{line}");
+        }
+        assert_eq!(text.matches("->").count(), 6);
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut voice = voice(5);
+        voice.operators[0].feedback_level = 7;
+        voice.routing.output[2] = FP::from(0.5);
+        voice.routing.feedback[2][1] = FP::from(0.25);
+        let text = svg(&voice);
+        assert!(text.starts_with("<svg ") && text.ends_with("</svg>\n"));
+        assert_eq!(text.matches("<rect").count(), NUM_OPERATORS);
+        // three modulations, three outputs and the two feedbacks
+        assert_eq!(text.matches("marker-end").count(), 8);
+        assert_eq!(text.matches("stroke-dasharray").count(), 2);
+        assert!(text.contains(">fb 7</text>") && text.contains(">50%</text>") && text.contains(">fb 25%</text>"));
+    }
+}
@@ -0,0 +1,217 @@
+//! dx7
+//!
+//! six operator voices with the 32 algorithms of the Yamaha DX7. The DX7
+//! numbers its operators from the carriers up, so that modulators have
+//! higher numbers; operators here are computed from the first, so DX7
+//! operator `n` is operator `6 - n` (from 0). `DX7_ALGORITHMS` lists the
+//! connections in DX7 numbers, as in the DX7 manual.
+
+use crate::fp::*;
+
+use super::voice::{FmVoice, Routing};
+
+pub const DX7_OPERATORS : usize = 6;
+pub const DX7_ALGORITHM_COUNT : usize = 32;
+
+pub type Dx7Voice = FmVoice<DX7_OPERATORS>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Dx7Algorithm {
+    pub routing : Routing<DX7_OPERATORS>,
+    /// operators (from 0) whose output feeds back and whose modulation
+    /// input it feeds; the same operator for all but algorithms 4 and 6,
+    /// where the loop runs through several operators
+    pub feedback : (usize, usize),
+}
+
+/// index of DX7 operator `n`
+pub const fn dx7_operator(n : usize) -> usize {
+    DX7_OPERATORS - n
+}
+
+/// full depth modulation for each `(modulator, carrier)` and full level for
+/// each carrier, with feedback from the output of the first operator of
+/// `feedback` into the second, all in DX7 numbers
+const fn algorithm(modulation : &[(usize, usize)], carriers : &[usize], feedback : (usize, usize)) -> Dx7Algorithm {
+    let mut routing = Routing::new();
+    let mut i = 0;
+    while i < modulation.len() {
+        let (src, dst) = modulation[i];
+        routing.modulation[dx7_operator(src)][dx7_operator(dst)] = FP_ONE;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < carriers.len() {
+        routing.output[dx7_operator(carriers[i])] = FP_ONE;
+        i += 1;
+    }
+    Dx7Algorithm { routing, feedback : (dx7_operator(feedback.0), dx7_operator(feedback.1)) }
+}
+
+pub const DX7_ALGORITHMS : [Dx7Algorithm; DX7_ALGORITHM_COUNT] = [
+    algorithm(&[(2, 1), (6, 5), (5, 4), (4, 3)], &[1, 3], (6, 6)),
+    algorithm(&[(2, 1), (6, 5), (5, 4), (4, 3)], &[1, 3], (2, 2)),
+    algorithm(&[(3, 2), (2, 1), (6, 5), (5, 4)], &[1, 4], (6, 6)),
+    algorithm(&[(3, 2), (2, 1), (6, 5), (5, 4)], &[1, 4], (4, 6)),
+    algorithm(&[(2, 1), (4, 3), (6, 5)], &[1, 3, 5], (6, 6)),
+    algorithm(&[(2, 1), (4, 3), (6, 5)], &[1, 3, 5], (5, 6)),
+    algorithm(&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (6, 6)),
+    algorithm(&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (4, 4)),
+    algorithm(&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (2, 2)),
+    algorithm(&[(3, 2), (2, 1), (5, 4), (6, 4)], &[1, 4], (3, 3)),
+    algorithm(&[(3, 2), (2, 1), (5, 4), (6, 4)], &[1, 4], (6, 6)),
+    algorithm(&[(2, 1), (4, 3), (5, 3), (6, 3)], &[1, 3], (2, 2)),
+    algorithm(&[(2, 1), (4, 3), (5, 3), (6, 3)], &[1, 3], (6, 6)),
+    algorithm(&[(2, 1), (4, 3), (5, 4), (6, 4)], &[1, 3], (6, 6)),
+    algorithm(&[(2, 1), (4, 3), (5, 4), (6, 4)], &[1, 3], (2, 2)),
+    algorithm(&[(2, 1), (3, 1), (5, 1), (4, 3), (6, 5)], &[1], (6, 6)),
+    algorithm(&[(2, 1), (3, 1), (5, 1), (4, 3), (6, 5)], &[1], (2, 2)),
+    algorithm(&[(2, 1), (3, 1), (4, 1), (5, 4), (6, 5)], &[1], (3, 3)),
+    algorithm(&[(3, 2), (2, 1), (6, 4), (6, 5)], &[1, 4, 5], (6, 6)),
+    algorithm(&[(3, 1), (3, 2), (5, 4), (6, 4)], &[1, 2, 4], (3, 3)),
+    algorithm(&[(3, 1), (3, 2), (6, 4), (6, 5)], &[1, 2, 4, 5], (3, 3)),
+    algorithm(&[(2, 1), (6, 3), (6, 4), (6, 5)], &[1, 3, 4, 5], (6, 6)),
+    algorithm(&[(3, 2), (6, 4), (6, 5)], &[1, 2, 4, 5], (6, 6)),
+    algorithm(&[(6, 3), (6, 4), (6, 5)], &[1, 2, 3, 4, 5], (6, 6)),
+    algorithm(&[(6, 4), (6, 5)], &[1, 2, 3, 4, 5], (6, 6)),
+    algorithm(&[(3, 2), (5, 4), (6, 4)], &[1, 2, 4], (6, 6)),
+    algorithm(&[(3, 2), (5, 4), (6, 4)], &[1, 2, 4], (3, 3)),
+    algorithm(&[(2, 1), (5, 4), (4, 3)], &[1, 3, 6], (5, 5)),
+    algorithm(&[(4, 3), (6, 5)], &[1, 2, 3, 5], (6, 6)),
+    algorithm(&[(5, 4), (4, 3)], &[1, 2, 3, 6], (5, 5)),
+    algorithm(&[(6, 5)], &[1, 2, 3, 4, 5], (6, 6)),
+    algorithm(&[], &[1, 2, 3, 4, 5, 6], (6, 6)),
+];
+
+impl Dx7Voice {
+    /// route the operators as DX7 `algorithm` (from 0) with its feedback at
+    /// `feedback_level`: the feedback level of an operator feeding back into
+    /// itself, or the depth of the feedback path of a loop, at the same
+    /// scale of 256 for full depth
+    pub fn set_dx7_algorithm(&mut self, algorithm : usize, feedback_level : u8) {
+        let algorithm = &DX7_ALGORITHMS[algorithm];
+        let (from, to) = algorithm.feedback;
+        self.routing = algorithm.routing;
+        for (idx, op) in self.operators.iter_mut().enumerate() {
+            op.feedback_level = if from == to && idx == to { feedback_level } else { 0 };
+        }
+        if from != to {
+            self.routing.feedback[from][to] = FP::raw((feedback_level as i32) << 8);
+        }
+    }
+
+    /// the feedback of the voice as `(from, to)` and its level, as
+    /// `set_dx7_algorithm` sets it: the first operator feeding back into
+    /// itself, or else the first feedback path
+    fn dx7_feedback(&self) -> Option<((usize, usize), u8)> {
+        if let Some(op) = self.operators.iter().position(|op| op.feedback_level != 0) {
+            return Some(((op, op), self.operators[op].feedback_level));
+        }
+        let feedback = &self.routing.feedback;
+        (0..DX7_OPERATORS).flat_map(|src| (0..src).map(move |dst| (src, dst)))
+            .find(|(src, dst)| feedback[*src][*dst] != FP_ZERO)
+            .map(|(src, dst)| ((src, dst), (feedback[src][dst].repr >> 8).clamp(0, 255) as u8))
+    }
+
+    /// the DX7 algorithm the routing is, if any; of those with the same
+    /// modulation and outputs, the one with the feedback where the voice
+    /// has it
+    pub fn dx7_algorithm(&self) -> Option<usize> {
+        let routing = &self.routing;
+        let mut matches = DX7_ALGORITHMS.iter().enumerate()
+            .filter(|(_, algorithm)| algorithm.routing.modulation == routing.modulation
+                && algorithm.routing.output == routing.output)
+            .map(|(idx, _)| idx);
+        let first = matches.next()?;
+        let feedback = self.dx7_feedback().map(|(ops, _)| ops);
+        Some(core::iter::once(first).chain(matches)
+            .find(|idx| Some(DX7_ALGORITHMS[*idx].feedback) == feedback)
+            .unwrap_or(first))
+    }
+
+    /// the feedback level `set_dx7_algorithm` was given
+    pub fn dx7_feedback_level(&self) -> u8 {
+        self.dx7_feedback().map_or(0, |(_, level)| level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::voice_pool::VoicePool;
+
+    #[test]
+    fn test_algorithms() {
+        let carriers = [2, 2, 2, 2, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+            1, 1, 3, 3, 4, 4, 4, 5, 5, 3, 3, 3, 4, 4, 5, 6];
+        let modulations = [4, 4, 4, 4, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4, 4, 5,
+            5, 5, 4, 4, 4, 4, 3, 3, 2, 3, 3, 3, 2, 2, 1, 0];
+        for (idx, algorithm) in DX7_ALGORITHMS.iter().enumerate() {
+            let routing = &algorithm.routing;
+            assert_eq!(routing.carriers().iter().filter(|c| **c).count(), carriers[idx], "algorithm {}", idx + 1);
+            let count = routing.modulation.iter().flatten().filter(|depth| **depth != FP_ZERO).count();
+            assert_eq!(count, modulations[idx], "algorithm {}", idx + 1);
+            // modulators are computed first
+            for (src, row) in routing.modulation.iter().enumerate() {
+                assert!(row[..=src].iter().all(|depth| *depth == FP_ZERO), "algorithm {}", idx + 1);
+            }
+        }
+        assert_eq!(DX7_ALGORITHMS[3].feedback, (2, 0));
+        assert_eq!(DX7_ALGORITHMS[5].feedback, (1, 0));
+        assert_eq!(DX7_ALGORITHMS[16].feedback, (4, 4));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_notation() {
+        use crate::synth::algorithm;
+        for algorithm in &DX7_ALGORITHMS {
+            assert_eq!(algorithm::validate(&algorithm.routing), Ok(()));
+        }
+        // DX7 6>5>4>3 and 2>1
+        assert_eq!(algorithm::to_text(&DX7_ALGORITHMS[0].routing).unwrap(), "1>2>3>4+5>6");
+        assert_eq!(algorithm::to_text(&DX7_ALGORITHMS[21].routing).unwrap(), "1>(2,3,4)+5>6");
+    }
+
+    #[test]
+    fn test_voice() {
+        let mut voice = Dx7Voice::new();
+        voice.set_dx7_algorithm(1, 100);
+        assert_eq!(voice.operators[dx7_operator(2)].feedback_level, 100);
+        assert_eq!(voice.operators.iter().filter(|op| op.feedback_level != 0).count(), 1);
+        assert_eq!(voice.dx7_algorithm(), Some(1));
+        voice.set_dx7_algorithm(0, 100);
+        assert_eq!(voice.dx7_algorithm(), Some(0));
+        voice.set_dx7_algorithm(0, 0);
+        assert_eq!(voice.dx7_algorithm(), Some(0));
+        voice.routing.output[0] = FP_ONE;
+        assert_eq!(voice.dx7_algorithm(), None);
+
+        // the loop of algorithm 4 runs from DX7 operator 4 back to 6
+        voice.set_dx7_algorithm(3, 100);
+        assert!(voice.operators.iter().all(|op| op.feedback_level == 0));
+        assert_eq!(voice.routing.feedback[dx7_operator(4)][dx7_operator(6)], FP::raw(100 << 8));
+        assert_eq!(voice.dx7_algorithm(), Some(3));
+        assert_eq!(voice.dx7_feedback_level(), 100);
+        voice.set_dx7_algorithm(2, 100);
+        assert_eq!(voice.routing.feedback, [[FP_ZERO; DX7_OPERATORS]; DX7_OPERATORS]);
+        assert_eq!(voice.dx7_algorithm(), Some(2));
+        voice.set_dx7_algorithm(5, 90);
+        assert_eq!(voice.dx7_algorithm(), Some(5));
+        assert_eq!(voice.dx7_feedback_level(), 90);
+
+        // every algorithm sounds, each carrier adding up to full scale
+        for algorithm in 0..DX7_ALGORITHM_COUNT {
+            let mut voice = Dx7Voice::new();
+            for op in &mut voice.operators {
+                op.env_gen.attack_rate = FP_ONE;
+            }
+            voice.set_dx7_algorithm(algorithm, 60);
+            let mut pool = VoicePool::new(voice);
+            pool.note_on(57, 127);
+            let peak = (0..4800).map(|_| pool.get_sample().abs()).fold(0.0, f32::max);
+            let carriers = voice.carriers().iter().filter(|c| **c).count() as f32;
+            assert!(peak > 0.1 && peak <= carriers * 1.01, "algorithm {} peak {peak}", algorithm + 1);
+        }
+    }
+}
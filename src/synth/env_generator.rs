@@ -30,6 +30,12 @@ pub struct EnvGenerator {
     state : EnvState
 }
 
+impl Default for EnvGenerator {
+    fn default() -> EnvGenerator {
+        EnvGenerator::new()
+    }
+}
+
 impl EnvGenerator {
     pub fn new() -> EnvGenerator {
         EnvGenerator {
@@ -58,6 +64,10 @@ impl EnvGenerator {
         self.state = EnvState::Release;
     }
 
+    pub fn state(&self) -> EnvState {
+        self.state
+    }
+
     pub fn get_sample(&mut self) -> FP {
         self.clock -= 1;
         if self.clock == 0 {
@@ -74,7 +84,7 @@ impl EnvGenerator {
 
     fn attack(&mut self) {
         // index counts down from XFACTOR to XFACTOR-3 in FP
-        self.index = self.index - self.attack_rate;
+        self.index -= self.attack_rate;
         self.level = OFFSET_UP - FP::exp(self.index);
 
         if self.level >= FP_ONE || self.index <= MIN_INDEX {
@@ -86,7 +96,7 @@ impl EnvGenerator {
 
     fn decay(&mut self) {
         // index counts down from INDEX_OFFSET to INDEX_OFFSET-3 in FP
-        self.index = self.index - self.decay_rate;
+        self.index -= self.decay_rate;
         self.level = FP::exp(self.index) - OFFSET_DN;
 
         if self.level <= self.sustain_level || self.index <= MIN_INDEX {
@@ -101,7 +111,7 @@ impl EnvGenerator {
 
     fn release(&mut self) {
         // index counts down from INDEX_OFFSET to INDEX_OFFSET-3 in FP
-        self.index = self.index - self.release_rate;
+        self.index -= self.release_rate;
         self.level = FP::exp(self.index) - OFFSET_DN;
 
         if self.level <= FP_ZERO || self.index <= MIN_INDEX {
@@ -127,13 +137,13 @@ impl EnvGenerator {
         self.index = idx;
     }
 
-    pub fn state_to_str(self) -> String {
+    pub fn state_to_str(self) -> &'static str {
         match self.state {
-            EnvState::Attack  => { String::from("Att") },
-            EnvState::Decay   => { String::from("Dec") },
-            EnvState::Idle    => { String::from("Idl") },
-            EnvState::Release => { String::from("Rel") },
-            EnvState::Sustain => { String::from("Sus") },
+            EnvState::Attack  => "Att",
+            EnvState::Decay   => "Dec",
+            EnvState::Idle    => "Idl",
+            EnvState::Release => "Rel",
+            EnvState::Sustain => "Sus",
         }
     }
 }
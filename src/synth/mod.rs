@@ -8,4 +8,30 @@ pub mod phase_generator;
 pub mod wave_generator;
 pub mod env_generator;
 pub mod operator;
-pub mod voice; 
\ No newline at end of file
+pub mod voice;
+pub mod dx7;
+pub mod tuning;
+pub mod ramp;
+pub mod pitch;
+pub mod note_stack;
+pub mod voice_pool;
+pub mod param;
+#[cfg(feature = "std")]
+pub mod algorithm;
+#[cfg(feature = "std")]
+pub mod diagram;
+#[cfg(feature = "std")]
+pub mod patch;
+#[cfg(feature = "std")]
+pub mod control;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod random;
+#[cfg(feature = "std")]
+pub mod morph;
+#[cfg(feature = "std")]
+pub mod resynth;
+#[cfg(feature = "std")]
+pub mod sysex;
+pub mod resample;
\ No newline at end of file
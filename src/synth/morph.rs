@@ -0,0 +1,147 @@
+//! morph
+//!
+//! crossfade between two patches. Continuous parameters are interpolated,
+//! envelope rates geometrically so that the middle sounds halfway; choices
+//! (waveforms, the glide mode, sustain) switch from `a` to `b` at
+//! `threshold`. The routing is crossfaded depth by depth and level by
+//! level, so between two algorithms modulators fade in and out. Applied to
+//! a playing voice only the parameters change: notes keep sounding and the
+//! operators ramp levels, feedback, tune and the routing over their
+//! smoothing time, so the amount can be automated.
+
+use super::param::{Param, Unit};
+use super::voice::Voice;
+use super::voice_pool::VoicePool;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Morph {
+    pub a : Voice,
+    pub b : Voice,
+    /// amount from which choices take the value of `b`
+    pub threshold : f32,
+}
+
+impl Morph {
+    pub fn new(a : Voice, b : Voice) -> Morph {
+        Morph { a, b, threshold : 0.5 }
+    }
+
+    /// value of `param` at `amount`, from 0 for `a` to 1 for `b`
+    pub fn value(&self, param : Param, amount : f32) -> f32 {
+        let amount = amount.clamp(0.0, 1.0);
+        let (a, b) = (param.get(&self.a), param.get(&self.b));
+        match param.unit() {
+            Unit::Choice(_) => if amount < self.threshold { a } else { b },
+            Unit::Rate if a > 0.0 && b > 0.0 => a * (b / a).powf(amount),
+            _ => a + (b - a) * amount,
+        }
+    }
+
+    /// a new voice at `amount`
+    pub fn voice(&self, amount : f32) -> Voice {
+        let mut voice = self.a;
+        self.apply(&mut voice, amount);
+        voice
+    }
+
+    /// set the parameters of a voice, which may be playing, to `amount`
+    pub fn apply(&self, voice : &mut Voice, amount : f32) {
+        for param in Param::all() {
+            param.set(voice, self.value(param, amount));
+        }
+    }
+
+    /// `apply` to all voices of a pool
+    pub fn apply_pool(&self, pool : &mut VoicePool, amount : f32) {
+        for param in Param::all() {
+            let value = self.value(param, amount);
+            for voice in pool.voices_mut() {
+                param.set(voice, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp::*;
+    use crate::synth::param::OpParam;
+    use crate::synth::wave_generator::WaveForm;
+
+    fn morph() -> Morph {
+        let mut a = Voice::new();
+        let mut b = Voice::new();
+        a.set_algorithm(1);
+        b.set_algorithm(6);
+        a.operators[0].total_level = 100;
+        b.operators[0].total_level = 200;
+        b.operators[1].phase_gen.tune = FP::from(2);
+        b.operators[2].wave_gen.waveform = WaveForm::Square;
+        a.operators[3].env_gen.attack_rate = FP::from(0.01);
+        b.operators[3].env_gen.attack_rate = FP::from(1);
+        a.operators[3].env_gen.decay_rate = FP::from(0.001);
+        b.operators[3].env_gen.decay_rate = FP::from(0.001);
+        Morph::new(a, b)
+    }
+
+    #[test]
+    fn test_values() {
+        let morph = morph();
+        let text = |voice : &Voice| crate::synth::patch::to_text(voice);
+        assert_eq!(text(&morph.voice(0.0)), text(&morph.a));
+        assert_eq!(text(&morph.voice(1.0)), text(&morph.b));
+        assert_eq!(text(&morph.voice(7.0)), text(&morph.b));
+
+        let half = morph.voice(0.5);
+        assert_eq!(half.operators[0].total_level, 150);
+        assert_eq!(Param::Op(1, OpParam::Tune).get(&half), 1.0);
+        let rate = Param::Op(3, OpParam::AttackRate).get(&half);
+        assert!((rate - 0.1).abs() < 0.001, "{rate}");
+        assert_eq!(Param::Op(3, OpParam::DecayRate).get(&half), Param::Op(3, OpParam::DecayRate).get(&morph.a));
+
+        // the routing crossfades
+        assert_eq!(morph.voice(0.0).algorithm(), Some(1));
+        assert_eq!(morph.voice(1.0).algorithm(), Some(6));
+        assert_eq!(half.algorithm(), None);
+        assert_eq!(Param::Mod(0, 2).get(&half), 0.5);
+        assert_eq!(Param::Mod(1, 2).get(&half), 1.0);
+        assert_eq!(Param::Op(0, OpParam::Output).get(&half), 0.5);
+        assert_eq!(Param::Op(3, OpParam::Output).get(&half), 1.0);
+
+        // choices switch at the threshold
+        assert_eq!(morph.voice(0.49).operators[2].wave_gen.waveform, WaveForm::FullSine);
+        let late = Morph { threshold : 0.9, ..morph };
+        assert_eq!(late.voice(0.8).operators[2].wave_gen.waveform, WaveForm::FullSine);
+        assert_eq!(late.voice(0.9).operators[2].wave_gen.waveform, WaveForm::Square);
+    }
+
+    #[test]
+    fn test_playing() {
+        let mut a = Voice::new();
+        a.operators[3].env_gen.attack_rate = FP::from(1);
+        a.operators[3].total_level = 60;
+        let mut b = a;
+        b.operators[3].total_level = 255;
+        let morph = Morph::new(a, b);
+
+        let mut pool = VoicePool::new(a);
+        pool.note_on(57, 127);
+        let peak = |pool : &mut VoicePool, samples| (0..samples)
+            .map(|_| pool.get_sample().abs()).fold(0.0, f32::max);
+        let quiet = peak(&mut pool, 4800);
+
+        // a sweep of the amount in small steps keeps the note and does not click
+        let mut last = pool.get_sample();
+        for step in 0..=100 {
+            morph.apply_pool(&mut pool, step as f32 / 100.0);
+            for _ in 0..48 {
+                let sample = pool.get_sample();
+                assert!((sample - last).abs() < 0.2);
+                last = sample;
+            }
+        }
+        assert!(pool.voices().any(|voice| voice.is_active()));
+        assert!(peak(&mut pool, 4800) > 2.0 * quiet);
+    }
+}
@@ -0,0 +1,75 @@
+//! note_stack
+//!
+//! the keys held down in a monophonic play mode, in the order they were
+//! pressed. The sounding key is picked from the stack by note priority.
+
+pub const STACK_SIZE : usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NoteStack {
+    keys : [u8; STACK_SIZE],
+    len : usize,
+}
+
+impl Default for NoteStack {
+    fn default() -> NoteStack {
+        NoteStack::new()
+    }
+}
+
+impl NoteStack {
+    pub fn new() -> NoteStack {
+        NoteStack {
+            keys : [0; STACK_SIZE],
+            len : 0,
+        }
+    }
+
+    /// add `key` as the most recent one; a key already held moves to the top.
+    /// When the stack is full the oldest key is dropped.
+    pub fn push(&mut self, key : u8) {
+        self.remove(key);
+        if self.len == STACK_SIZE {
+            self.keys.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.keys[self.len] = key;
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, key : u8) {
+        if let Some(pos) = self.keys[..self.len].iter().position(|k| *k == key) {
+            self.keys.copy_within(pos + 1..self.len, pos);
+            self.len -= 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, key : u8) -> bool {
+        self.keys[..self.len].contains(&key)
+    }
+
+    /// the key that should sound
+    pub fn top(&self, priority : NotePriority) -> Option<u8> {
+        let keys = self.keys[..self.len].iter().copied();
+        match priority {
+            NotePriority::Last => keys.last(),
+            NotePriority::Low  => keys.min(),
+            NotePriority::High => keys.max(),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 //! operator
 //!
 //! models an FM operator
+#[cfg(feature = "rodio")]
 use std::time::Duration;
+#[cfg(feature = "rodio")]
 use rodio::source::Source;
 
 use crate::fp::*;
@@ -9,6 +11,11 @@ use crate::fp::*;
 use super::phase_generator::*;
 use super::wave_generator::*;
 use super::env_generator::*;
+use super::ramp::Smoother;
+use super::SAMPLE_FREQ;
+
+/// default time over which level, feedback and tune changes are smoothed (10ms)
+pub const SMOOTHING_SAMPLES : u32 = SAMPLE_FREQ / 100;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Operator {
@@ -18,9 +25,21 @@ pub struct Operator {
 
     pub total_level : u8,
     pub feedback_level : u8,
+    /// samples to ramp to a new total level, feedback level or tune
+    pub smoothing : u32,
 
     pub mod_input : FP,
     feedback : FP,
+
+    level_smoother : Smoother,
+    feedback_smoother : Smoother,
+    tune_smoother : Smoother,
+}
+
+impl Default for Operator {
+    fn default() -> Operator {
+        Operator::new()
+    }
 }
 
 impl Operator {
@@ -32,25 +51,42 @@ impl Operator {
 
             total_level : 255,
             feedback_level : 0,
+            smoothing : SMOOTHING_SAMPLES,
 
             mod_input : FP_ZERO,
             feedback : FP_ZERO,
+
+            level_smoother : Smoother::new(FP::from(255u8)),
+            feedback_smoother : Smoother::new(FP_ZERO),
+            tune_smoother : Smoother::new(FP_ZERO),
         }
     }
 
+    /// take over the current levels and tune without ramping,
+    /// for when the operator is silent anyway
+    pub fn settle(&mut self) {
+        self.level_smoother.settle(FP::from(self.total_level));
+        self.feedback_smoother.settle(FP::from(self.feedback_level));
+        self.tune_smoother.settle(self.phase_gen.tune);
+    }
+
     pub fn get_sample(&mut self) -> FP {
-        let phase       = self.phase_gen.update(self.mod_input + self.feedback);
+        let tune        = self.tune_smoother.next(self.phase_gen.tune, self.smoothing);
+        let level       = self.level_smoother.next(FP::from(self.total_level), self.smoothing);
+        let fb_level    = self.feedback_smoother.next(FP::from(self.feedback_level), self.smoothing);
+
+        let phase       = self.phase_gen.advance(tune, self.mod_input + self.feedback);
         let wave_sample = self.wave_gen.generate(phase);
         let env_level   = self.env_gen.get_sample();
 
         let output = 
-            if env_level == FP_ZERO || self.total_level == 0 {
+            if env_level == FP_ZERO || level == FP_ZERO {
                 FP_ZERO
             } else {
-                wave_sample * env_level * FP::from(self.total_level) >> 8
+                (wave_sample * env_level * level) >> 8
             };
 
-        self.feedback = (output * FP::from(self.feedback_level)) >> 8;
+        self.feedback = (output * fb_level) >> 8;
 
         return output;
     }
@@ -64,6 +100,7 @@ impl Iterator for Operator {
     }
 }
 
+#[cfg(feature = "rodio")]
 impl Source for Operator {
     fn channels(&self) -> u16 {
         return 1;
@@ -80,4 +117,53 @@ impl Source for Operator {
      fn total_duration(&self) -> Option<Duration> {
         return None;
      }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing() -> Operator {
+        let mut op = Operator::new();
+        op.env_gen.attack_rate = FP::from(4);
+        op.phase_gen.flog2 = FP::from(1000.0f64.log2());
+        op.env_gen.open();
+        for _ in 0..1000 {
+            op.get_sample();
+        }
+        op
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut op = playing();
+        assert!(!op.level_smoother.is_active());
+
+        // level drops to 0 over the smoothing time, not at once
+        op.total_level = 0;
+        let peak = |op : &mut Operator, samples : u32| {
+            (0..samples).map(|_| op.get_sample().repr.abs()).max().unwrap()
+        };
+        assert!(peak(&mut op, 48) > 0xE000);
+        assert!(peak(&mut op, SMOOTHING_SAMPLES - 48) > 0);
+        assert!(!op.level_smoother.is_active());
+        assert_eq!(peak(&mut op, 100), 0);
+
+        // tune moves the phase increment gradually
+        op.total_level = 255;
+        op.phase_gen.tune = FP_ONE;
+        op.get_sample();
+        let phase = op.phase_gen.phase;
+        op.get_sample();
+        let step = (op.phase_gen.phase - phase).frac();
+        assert!(step.repr < 1500 && step.repr > 1365, "starts near 1kHz");
+
+        // a silent operator takes new values at once
+        let mut op = Operator::new();
+        op.total_level = 0;
+        op.settle();
+        assert!(!op.level_smoother.is_active());
+        assert_eq!(op.get_sample(), FP_ZERO);
+        assert!(!op.level_smoother.is_active());
+    }
+}
@@ -0,0 +1,488 @@
+//! param
+//!
+//! registry of the patch parameters of a voice: every settable field of
+//! the voice and of its operators' generators, with a stable id, a name,
+//! range, default, unit and conversion to and from display text.
+//! UIs, automation, MIDI mapping and patch files all address parameters
+//! through here instead of naming fields themselves.
+//!
+//! Values are f32 in the unit of the field: levels 0..255, tune and rates
+//! as their FP value, waveform, algorithm and switches as an index.
+//! Modulation depths, feedback paths and operator outputs make up the
+//! routing; setting the algorithm replaces the routing with its preset, so
+//! it goes before them.
+//!
+//! The registry is that of the four operator `Voice` only. Voices of other
+//! operator counts, as `Dx7Voice`, have no parameters here, so no patch
+//! text, plugin or MIDI mapping either: their fields are set directly, or
+//! from DX7 sysex.
+
+use crate::fp::*;
+
+use super::voice::*;
+use super::pitch::GlideMode;
+use super::wave_generator::WAVEFORMS;
+use super::SAMPLE_FREQ;
+
+/// operators of `Voice`, the only voice with parameters
+pub const NUM_OPERATORS : usize = 4;
+
+/// ids of operator parameters are `OP_ID_BASE * operator number + field id`
+const OP_ID_BASE : u32 = 100;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpParam {
+    WaveForm,
+    TotalLevel,
+    FeedbackLevel,
+    Tune,
+    AttackRate,
+    DecayRate,
+    SustainLevel,
+    ReleaseRate,
+    IsSustained,
+    Smoothing,
+    /// level at which the operator is heard
+    Output,
+}
+
+const OP_PARAMS : [OpParam; 11] = [
+    OpParam::WaveForm,
+    OpParam::TotalLevel,
+    OpParam::FeedbackLevel,
+    OpParam::Tune,
+    OpParam::AttackRate,
+    OpParam::DecayRate,
+    OpParam::SustainLevel,
+    OpParam::ReleaseRate,
+    OpParam::IsSustained,
+    OpParam::Smoothing,
+    OpParam::Output,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Param {
+    Algorithm,
+    GlideMode,
+    GlideTime,
+    BendRange,
+    /// depth at which an operator modulates a later one, indices from 0
+    Mod(usize, usize),
+    /// depth at which an operator modulates an earlier one a sample late,
+    /// indices from 0
+    Feedback(usize, usize),
+    /// operator index from 0, parameter
+    Op(usize, OpParam),
+}
+
+const VOICE_PARAMS : [Param; 4] = [
+    Param::Algorithm,
+    Param::GlideMode,
+    Param::GlideTime,
+    Param::BendRange,
+];
+
+/// the part of the engine a parameter belongs to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Component {
+    Voice,
+    Operator,
+    PhaseGenerator,
+    WaveGenerator,
+    EnvGenerator,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Unit {
+    /// plain number, as for the 0..255 levels
+    Number,
+    /// fraction of full scale, shown as percent
+    Fraction,
+    /// log2 of a frequency ratio
+    Octaves,
+    /// envelope index change per envelope tick
+    Rate,
+    Seconds,
+    Semitones,
+    /// stored in samples, shown in milliseconds
+    Samples,
+    /// one of a list of names
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParamInfo {
+    /// never changes between versions, unlike the position in `Param::all()`
+    pub id : u32,
+    /// field name, as used in the parameter name
+    pub key : &'static str,
+    pub label : &'static str,
+    pub component : Component,
+    pub min : f32,
+    pub max : f32,
+    /// value in `Voice::new()`
+    pub default : f32,
+    pub unit : Unit,
+    /// only whole values are meaningful
+    pub stepped : bool,
+}
+
+const WAVEFORM_NAMES : [&str; 8] = [
+    "FullSine", "HalfSine", "DblHalfSine", "DblQuartSine",
+    "FastSine", "FastHalfSine", "Sawish", "Square"
+];
+/// the last one is any routing that is not a preset
+const ALGORITHM_NAMES : [&str; ALGORITHM_COUNT + 1] = ["1", "2", "3", "4", "5", "6", "7", "8", "custom"];
+const GLIDE_MODE_NAMES : [&str; 2] = ["ConstantTime", "ConstantRate"];
+const SWITCH_NAMES : [&str; 2] = ["off", "on"];
+
+impl OpParam {
+    fn id(self) -> u32 {
+        match self {
+            OpParam::WaveForm      => 1,
+            OpParam::TotalLevel    => 2,
+            OpParam::FeedbackLevel => 3,
+            OpParam::Tune          => 4,
+            OpParam::AttackRate    => 5,
+            OpParam::DecayRate     => 6,
+            OpParam::SustainLevel  => 7,
+            OpParam::ReleaseRate   => 8,
+            OpParam::IsSustained   => 9,
+            OpParam::Smoothing     => 10,
+            OpParam::Output        => 11,
+        }
+    }
+}
+
+impl Param {
+    /// every parameter of a voice
+    pub fn all() -> impl Iterator<Item = Param> {
+        VOICE_PARAMS.into_iter()
+            .chain((0..NUM_OPERATORS).flat_map(|src| (src + 1..NUM_OPERATORS).map(move |dst| Param::Mod(src, dst))))
+            .chain((0..NUM_OPERATORS).flat_map(|src| (0..src).map(move |dst| Param::Feedback(src, dst))))
+            .chain((0..NUM_OPERATORS).flat_map(|op| OP_PARAMS.into_iter().map(move |p| Param::Op(op, p))))
+    }
+
+    pub fn info(self) -> ParamInfo {
+        ParamInfo {
+            default : self.get(&super::voice::Voice::new()),
+            ..self.meta()
+        }
+    }
+
+    /// info without the default, which takes a voice to find
+    fn meta(self) -> ParamInfo {
+        use Component::*;
+        let (id, key, label, component, min, max, unit, stepped) = match self {
+            Param::Algorithm => (1, "algorithm", "Algorithm", Voice,
+                0.0, ALGORITHM_COUNT as f32, Unit::Choice(&ALGORITHM_NAMES), true),
+            Param::GlideMode => (2, "glide_mode", "Glide mode", Voice,
+                0.0, 1.0, Unit::Choice(&GLIDE_MODE_NAMES), true),
+            Param::GlideTime => (3, "glide_time", "Glide time", Voice,
+                0.0, 10.0, Unit::Seconds, false),
+            Param::BendRange => (4, "bend_range", "Bend range", Voice,
+                0.0, 24.0, Unit::Semitones, true),
+            Param::Mod(src, dst) => (10 * (src as u32 + 1) + dst as u32 + 1, "mod", "Modulation", Voice,
+                0.0, 1.0, Unit::Fraction, false),
+            // the ids modulation leaves free, as the source comes after
+            Param::Feedback(src, dst) => (10 * (src as u32 + 1) + dst as u32 + 1, "feedback", "Feedback", Voice,
+                0.0, 1.0, Unit::Fraction, false),
+            Param::Op(op, p) => {
+                let (key, label, component, min, max, unit, stepped) = match p {
+                    OpParam::WaveForm => ("waveform", "Waveform", WaveGenerator,
+                        0.0, (WAVEFORMS.len() - 1) as f32, Unit::Choice(&WAVEFORM_NAMES), true),
+                    OpParam::TotalLevel => ("total_level", "Total level", Operator,
+                        0.0, 255.0, Unit::Number, true),
+                    OpParam::FeedbackLevel => ("feedback_level", "Feedback level", Operator,
+                        0.0, 255.0, Unit::Number, true),
+                    OpParam::Tune => ("tune", "Tune", PhaseGenerator,
+                        -4.0, 4.0, Unit::Octaves, false),
+                    OpParam::AttackRate => ("attack_rate", "Attack rate", EnvGenerator,
+                        0.0, 1.0, Unit::Rate, false),
+                    OpParam::DecayRate => ("decay_rate", "Decay rate", EnvGenerator,
+                        0.0, 1.0, Unit::Rate, false),
+                    OpParam::SustainLevel => ("sustain_level", "Sustain level", EnvGenerator,
+                        0.0, 1.0, Unit::Fraction, false),
+                    OpParam::ReleaseRate => ("release_rate", "Release rate", EnvGenerator,
+                        0.0, 1.0, Unit::Rate, false),
+                    OpParam::IsSustained => ("is_sustained", "Sustained", EnvGenerator,
+                        0.0, 1.0, Unit::Choice(&SWITCH_NAMES), true),
+                    OpParam::Smoothing => ("smoothing", "Smoothing", Operator,
+                        0.0, SAMPLE_FREQ as f32, Unit::Samples, true),
+                    OpParam::Output => ("output", "Output", Operator,
+                        0.0, 1.0, Unit::Fraction, false),
+                };
+                (OP_ID_BASE * (op as u32 + 1) + p.id(), key, label, component, min, max, unit, stepped)
+            }
+        };
+
+        ParamInfo { id, key, label, component, min, max, unit, stepped, default : 0.0 }
+    }
+
+    pub fn id(self) -> u32 {
+        self.meta().id
+    }
+
+    pub fn from_id(id : u32) -> Option<Param> {
+        Param::all().find(|p| p.id() == id)
+    }
+
+    /// name as in patch files, operators are numbered from 1: "op2.tune",
+    /// "mod1_2" for the depth at which op1 modulates op2, "fb4_1" for the
+    /// feedback path from op4 into op1
+    #[cfg(feature = "std")]
+    pub fn name(self) -> String {
+        match self {
+            Param::Mod(src, dst) => format!("mod{}_{}", src + 1, dst + 1),
+            Param::Feedback(src, dst) => format!("fb{}_{}", src + 1, dst + 1),
+            Param::Op(op, _) => format!("op{}.{}", op + 1, self.meta().key),
+            _ => String::from(self.meta().key),
+        }
+    }
+
+    /// name for hosts and UIs, unique among the parameters: "Op 2 Tune",
+    /// "Mod 1>3", "Feedback 4>1"
+    #[cfg(feature = "std")]
+    pub fn display_name(self) -> String {
+        match self {
+            Param::Mod(src, dst) => format!("Mod {}>{}", src + 1, dst + 1),
+            Param::Feedback(src, dst) => format!("Feedback {}>{}", src + 1, dst + 1),
+            Param::Op(op, _) => format!("Op {} {}", op + 1, self.meta().label),
+            _ => String::from(self.meta().label),
+        }
+    }
+
+    /// group of the parameter for hosts: "op2", "routing" for the depths
+    /// between operators, empty for the rest of the voice
+    #[cfg(feature = "std")]
+    pub fn module(self) -> String {
+        match self {
+            Param::Mod(..) | Param::Feedback(..) => String::from("routing"),
+            Param::Op(op, _) => format!("op{}", op + 1),
+            _ => String::new(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_name(name : &str) -> Option<Param> {
+        Param::all().find(|p| p.name() == name)
+    }
+
+    /// lowest and highest value
+    pub fn range(self) -> (f32, f32) {
+        let info = self.meta();
+        (info.min, info.max)
+    }
+
+    pub fn unit(self) -> Unit {
+        self.meta().unit
+    }
+
+    /// `value` as text for display, in the unit of the parameter
+    #[cfg(feature = "std")]
+    pub fn display(self, value : f32) -> String {
+        match self.meta().unit {
+            Unit::Number    => format!("{}", value.round()),
+            Unit::Fraction  => format!("{:.1} %", value * 100.0),
+            Unit::Octaves   => format!("{value:+.4} oct"),
+            Unit::Rate      => format!("{value:.5}"),
+            Unit::Seconds   => format!("{value:.3} s"),
+            Unit::Semitones => format!("{} st", value.round()),
+            Unit::Samples   => format!("{:.1} ms", value * 1000.0 / SAMPLE_FREQ as f32),
+            Unit::Choice(names) => String::from(
+                names.get(value.round().max(0.0) as usize).copied().unwrap_or("?")),
+        }
+    }
+
+    /// value from text as made by `display`; the unit may be left out
+    #[cfg(feature = "std")]
+    pub fn parse(self, text : &str) -> Option<f32> {
+        let text = text.trim();
+        let number = |suffix : &str| text.trim_end_matches(suffix).trim().parse::<f32>().ok();
+
+        match self.meta().unit {
+            Unit::Number | Unit::Rate => number(""),
+            Unit::Fraction  => if text.ends_with('%') { number("%").map(|v| v / 100.0) } else { number("") },
+            Unit::Octaves   => number("oct"),
+            Unit::Seconds   => number("s"),
+            Unit::Semitones => number("st"),
+            Unit::Samples   => if text.ends_with("ms") {
+                number("ms").map(|v| (v * SAMPLE_FREQ as f32 / 1000.0).round())
+            } else {
+                number("")
+            },
+            Unit::Choice(names) => names.iter().position(|n| n.eq_ignore_ascii_case(text))
+                .map(|idx| idx as f32),
+        }
+    }
+
+    pub fn get(self, voice : &Voice) -> f32 {
+        match self {
+            Param::Algorithm => voice.algorithm().unwrap_or(ALGORITHM_COUNT) as f32,
+            Param::GlideMode => (voice.pitch.glide_mode == GlideMode::ConstantRate) as u8 as f32,
+            Param::GlideTime => voice.pitch.glide_time.to_f32(),
+            Param::BendRange => voice.pitch.bend_range as f32,
+            Param::Mod(src, dst) => voice.routing.modulation[src][dst].to_f32(),
+            Param::Feedback(src, dst) => voice.routing.feedback[src][dst].to_f32(),
+            Param::Op(idx, p) => {
+                let op = &voice.operators[idx];
+                match p {
+                    OpParam::WaveForm      => op.wave_gen.waveform as usize as f32,
+                    OpParam::TotalLevel    => op.total_level as f32,
+                    OpParam::FeedbackLevel => op.feedback_level as f32,
+                    OpParam::Tune          => op.phase_gen.tune.to_f32(),
+                    OpParam::AttackRate    => op.env_gen.attack_rate.to_f32(),
+                    OpParam::DecayRate     => op.env_gen.decay_rate.to_f32(),
+                    OpParam::SustainLevel  => op.env_gen.sustain_level.to_f32(),
+                    OpParam::ReleaseRate   => op.env_gen.release_rate.to_f32(),
+                    OpParam::IsSustained   => op.env_gen.is_sustained as u8 as f32,
+                    OpParam::Smoothing     => op.smoothing as f32,
+                    OpParam::Output        => voice.routing.output[idx].to_f32(),
+                }
+            }
+        }
+    }
+
+    /// set `value`, clamped to the range of the parameter
+    pub fn set(self, voice : &mut Voice, value : f32) {
+        let (min, max) = self.range();
+        let value = value.clamp(min, max);
+        let index = round_index(value);
+
+        match self {
+            // "custom" keeps the routing as it is
+            Param::Algorithm => if index < ALGORITHM_COUNT {
+                voice.set_algorithm(index);
+            },
+            Param::GlideMode => voice.pitch.glide_mode =
+                if index == 0 { GlideMode::ConstantTime } else { GlideMode::ConstantRate },
+            Param::GlideTime => voice.pitch.glide_time = FP::from(value),
+            Param::BendRange => voice.pitch.bend_range = index as u8,
+            Param::Mod(src, dst) => voice.routing.modulation[src][dst] = FP::from(value),
+            Param::Feedback(src, dst) => voice.routing.feedback[src][dst] = FP::from(value),
+            Param::Op(idx, p) => {
+                let op = &mut voice.operators[idx];
+                match p {
+                    OpParam::WaveForm      => op.wave_gen.waveform = WAVEFORMS[index],
+                    OpParam::TotalLevel    => op.total_level = index as u8,
+                    OpParam::FeedbackLevel => op.feedback_level = index as u8,
+                    OpParam::Tune          => op.phase_gen.tune = FP::from(value),
+                    OpParam::AttackRate    => op.env_gen.attack_rate = FP::from(value),
+                    OpParam::DecayRate     => op.env_gen.decay_rate = FP::from(value),
+                    OpParam::SustainLevel  => op.env_gen.sustain_level = FP::from(value),
+                    OpParam::ReleaseRate   => op.env_gen.release_rate = FP::from(value),
+                    OpParam::IsSustained   => op.env_gen.is_sustained = index != 0,
+                    OpParam::Smoothing     => op.smoothing = index as u32,
+                    OpParam::Output        => voice.routing.output[idx] = FP::from(value),
+                }
+            }
+        }
+    }
+}
+
+/// `value` rounded half away from zero as `f32::round`, which core lacks;
+/// negative values give 0
+fn round_index(value : f32) -> usize {
+    let int = value as usize;
+    if value - int as f32 >= 0.5 { int + 1 } else { int }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let params : Vec<Param> = Param::all().collect();
+        let mods = NUM_OPERATORS * (NUM_OPERATORS - 1) / 2;
+        assert_eq!(params.len(), VOICE_PARAMS.len() + 2 * mods + NUM_OPERATORS * OP_PARAMS.len());
+
+        // ids and names are unique and lead back to the parameter
+        for param in &params {
+            assert_eq!(Param::from_id(param.id()), Some(*param));
+            assert_eq!(Param::from_name(&param.name()), Some(*param));
+            assert_eq!(params.iter().filter(|p| p.display_name() == param.display_name()).count(), 1,
+                "{}", param.display_name());
+            let info = param.info();
+            assert!(info.min <= info.default && info.default <= info.max, "{}", param.name());
+        }
+
+        // ids are stable
+        assert_eq!(Param::Algorithm.id(), 1);
+        assert_eq!(Param::Op(1, OpParam::TotalLevel).id(), 202);
+        assert_eq!(Param::Op(3, OpParam::Tune).name(), "op4.tune");
+        assert_eq!(Param::Mod(0, 2).id(), 13);
+        assert_eq!(Param::Mod(0, 2).name(), "mod1_3");
+        assert_eq!(Param::Feedback(3, 0).id(), 41);
+        assert_eq!(Param::Feedback(3, 0).name(), "fb4_1");
+        assert_eq!(Param::Mod(0, 2).display_name(), "Mod 1>3");
+        assert_eq!(Param::Feedback(3, 0).display_name(), "Feedback 4>1");
+        assert_eq!(Param::Feedback(3, 0).module(), "routing");
+        assert_eq!(Param::Op(1, OpParam::Tune).display_name(), "Op 2 Tune");
+        assert_eq!(Param::Op(1, OpParam::Tune).module(), "op2");
+        assert_eq!(Param::Op(3, OpParam::Output).id(), 411);
+
+        let info = Param::Op(0, OpParam::TotalLevel).info();
+        assert_eq!((info.min, info.max, info.default), (0.0, 255.0, 255.0));
+        assert_eq!(info.component, Component::Operator);
+        assert_eq!(Param::Op(0, OpParam::Tune).info().component, Component::PhaseGenerator);
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (Param::Op(0, OpParam::WaveForm), 7.0, "Square"),
+            (Param::Op(0, OpParam::TotalLevel), 32.0, "32"),
+            (Param::Op(0, OpParam::Tune), -1.0, "-1.0000 oct"),
+            (Param::Op(0, OpParam::SustainLevel), 0.5, "50.0 %"),
+            (Param::Op(0, OpParam::IsSustained), 1.0, "on"),
+            (Param::Op(0, OpParam::Smoothing), 480.0, "10.0 ms"),
+            (Param::Algorithm, 4.0, "5"),
+            (Param::Algorithm, 8.0, "custom"),
+            (Param::Mod(1, 2), 0.25, "25.0 %"),
+            (Param::GlideTime, 0.25, "0.250 s"),
+            (Param::BendRange, 2.0, "2 st"),
+        ];
+        for (param, value, text) in cases {
+            assert_eq!(param.display(value), text);
+            assert_eq!(param.parse(text), Some(value), "{text}");
+        }
+        assert_eq!(Param::Op(0, OpParam::Tune).parse("0.5"), Some(0.5));
+        assert_eq!(Param::Op(0, OpParam::WaveForm).parse("sawish"), Some(6.0));
+        assert_eq!(Param::Op(0, OpParam::WaveForm).parse("Triangle"), None);
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut voice = Voice::new();
+        Param::Algorithm.set(&mut voice, 3.0);
+        assert_eq!(Param::Op(0, OpParam::Output).get(&voice), 1.0);
+        assert_eq!(Param::Mod(1, 2).get(&voice), 1.0);
+
+        Param::Mod(0, 3).set(&mut voice, 0.5);
+        assert_eq!(voice.routing.modulation[0][3], FP::from(0.5));
+        assert_eq!(Param::Algorithm.get(&voice), 8.0);
+        Param::Algorithm.set(&mut voice, 8.0);
+        assert_eq!(Param::Mod(0, 3).get(&voice), 0.5);
+
+        Param::Mod(0, 3).set(&mut voice, 0.0);
+        assert_eq!(Param::Algorithm.get(&voice), 3.0);
+        Param::Op(3, OpParam::Output).set(&mut voice, 2.0);
+        assert_eq!(voice.routing.output[3], FP_ONE);
+
+        // a feedback path makes the routing custom, a preset clears it
+        Param::Feedback(3, 1).set(&mut voice, 0.25);
+        assert_eq!(voice.routing.feedback[3][1], FP::from(0.25));
+        assert_eq!(Param::Algorithm.get(&voice), 8.0);
+        Param::Algorithm.set(&mut voice, 3.0);
+        assert_eq!(Param::Feedback(3, 1).get(&voice), 0.0);
+    }
+
+    #[test]
+    fn test_round_index() {
+        for value in [0.0, 0.49999997, 0.5, 1.4999999, 2.5, 7.0, 254.5, 255.0] {
+            assert_eq!(round_index(value), value.round() as usize, "{value}");
+        }
+        assert_eq!(round_index(-1.0), 0);
+    }
+}
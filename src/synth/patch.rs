@@ -0,0 +1,137 @@
+//! patch
+//!
+//! text format for the parameters of a voice, one `name = value` per line:
+//!
+//! ```text
+//! algorithm = 0
+//! op1.total_level = 32
+//! op1.tune = -1
+//! ```
+//!
+//! Parameters that are left out keep their `Voice::new()` value,
+//! lines starting with '#' are comments. The algorithm is set before the
+//! other lines wherever it stands, as it replaces the routing.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::voice::*;
+use super::param::*;
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(std::io::Error),
+    /// line number (from 1) of a line that is not `name = value`
+    Syntax(usize),
+    UnknownParam(String),
+    BadValue(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(e)           => write!(f, "i/o error: {e}"),
+            PatchError::Syntax(line)    => write!(f, "line {line}: expected 'name = value'"),
+            PatchError::UnknownParam(s) => write!(f, "unknown parameter '{s}'"),
+            PatchError::BadValue(s)     => write!(f, "not a value: '{s}'"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<std::io::Error> for PatchError {
+    fn from(e : std::io::Error) -> Self {
+        PatchError::Io(e)
+    }
+}
+
+/// all parameters of `voice`, one per line
+pub fn to_text(voice : &Voice) -> String {
+    Param::all()
+        .map(|param| format!("{} = {}\n", param.name(), param.get(voice)))
+        .collect()
+}
+
+/// a voice with the parameters in `text`
+pub fn parse(text : &str) -> Result<Voice, PatchError> {
+    let mut values = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        values.extend(parse_line(idx + 1, line)?);
+    }
+    Ok(from_values(&values))
+}
+
+/// a voice with `values` set in order, but the algorithm first so that it
+/// does not undo the depths and outputs before it
+pub fn from_values(values : &[(Param, f32)]) -> Voice {
+    let mut voice = Voice::new();
+    let (algorithm, rest) : (Vec<_>, Vec<_>) = values.iter().partition(|(param, _)| *param == Param::Algorithm);
+    for (param, value) in algorithm.into_iter().chain(rest) {
+        param.set(&mut voice, value);
+    }
+    voice
+}
+
+/// `name = value`, None for blank and comment lines
+pub fn parse_line(line_nr : usize, line : &str) -> Result<Option<(Param, f32)>, PatchError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (name, value) = line.split_once('=').ok_or(PatchError::Syntax(line_nr))?;
+    let (name, value) = (name.trim(), value.trim());
+    let param = Param::from_name(name).ok_or_else(|| PatchError::UnknownParam(name.to_string()))?;
+    let value = value.parse::<f32>().map_err(|_| PatchError::BadValue(value.to_string()))?;
+    Ok(Some((param, value)))
+}
+
+pub fn save<P : AsRef<Path>>(voice : &Voice, path : P) -> Result<(), PatchError> {
+    Ok(fs::write(path, to_text(voice))?)
+}
+
+pub fn load<P : AsRef<Path>>(path : P) -> Result<Voice, PatchError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp::*;
+    use crate::synth::wave_generator::WaveForm;
+
+    #[test]
+    fn test_patch() {
+        let mut voice = Voice::new();
+        voice.set_algorithm(5);
+        voice.operators[1].wave_gen.waveform = WaveForm::Square;
+        voice.operators[1].total_level = 32;
+        voice.operators[1].phase_gen.tune = FP::from(-1);
+        voice.operators[2].phase_gen.tune = FP::from(0.5833);
+        voice.operators[3].env_gen.release_rate = FP::raw(20);
+        voice.operators[3].env_gen.is_sustained = false;
+
+        let text = to_text(&voice);
+        assert!(text.contains("op2.waveform = 7\n"));
+        assert!(text.contains("op2.tune = -1\n"));
+
+        let copy = parse(&text).unwrap();
+        assert_eq!(to_text(&copy), text);
+        assert_eq!(copy.operators[3].env_gen.release_rate, FP::raw(20));
+        assert_eq!(copy.operators[2].phase_gen.tune, FP::from(0.5833));
+
+        let voice = parse("# comment\n\nop4.total_level = 300\n").unwrap();
+        assert_eq!(voice.operators[3].total_level, 255);
+
+        // the algorithm does not reset a depth given before it
+        let voice = parse("mod1_4 = 0.5\nalgorithm = 0\n").unwrap();
+        assert_eq!(voice.routing.modulation[0][3], FP::from(0.5));
+        assert_eq!(voice.routing.modulation[0][1], FP_ONE);
+
+        assert!(matches!(parse("op5.tune = 1"), Err(PatchError::UnknownParam(_))));
+        assert!(matches!(parse("op1.tune = x"), Err(PatchError::BadValue(_))));
+        assert!(matches!(parse("\nop1.tune 1"), Err(PatchError::Syntax(2))));
+    }
+}
@@ -8,6 +8,12 @@ pub struct PhaseGenerator {
     pub tune: FP, // this is the log2 of "mult"
 }
 
+impl Default for PhaseGenerator {
+    fn default() -> PhaseGenerator {
+        PhaseGenerator::new()
+    }
+}
+
 impl PhaseGenerator {
     pub fn new() -> PhaseGenerator {
         PhaseGenerator {
@@ -18,6 +24,11 @@ impl PhaseGenerator {
     }
 
     pub fn update(&mut self, m: FP) -> FP {
+        self.advance(self.tune, m)
+    }
+
+    /// update with `tune` instead of the tune field
+    pub fn advance(&mut self, tune: FP, m: FP) -> FP {
         // flog2 is the log2 of the freq in FP,
         // int is octave, frac is note within octave 
         // (Basically this is the 1V/Oct input)
@@ -25,7 +36,7 @@ impl PhaseGenerator {
         // d(wt) = freq / sample_freq
         // = exp2[ log2(freq) - log2(sample_freq) ]
 
-        let phase_inc = FP::exp(self.flog2 + self.tune - LOG2_SF);
+        let phase_inc = FP::exp(self.flog2 + tune - LOG2_SF);
         self.phase = (self.phase + phase_inc).frac();
 
         // m is the modulation signal from the modulating operator
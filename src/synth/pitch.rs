@@ -0,0 +1,175 @@
+//! pitch
+//!
+//! the log2 frequency of a voice: the played note, gliding from the
+//! previous note when portamento is on, plus pitch bend.
+//! Both are ramped per sample, so neither steps audibly.
+
+use crate::fp::*;
+use super::ramp::Ramp;
+use super::SAMPLE_FREQ;
+
+/// pitch bend changes are spread over this many samples (1ms)
+const BEND_SAMPLES : u32 = SAMPLE_FREQ / 1000;
+
+/// the full scale of a (14 bit, centered) MIDI pitch bend value
+pub const BEND_MAX : i32 = 8192;
+
+const SEMITONES : i64 = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GlideMode {
+    /// every glide takes `glide_time` seconds
+    ConstantTime,
+    /// glides take `glide_time` seconds per octave
+    ConstantRate,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Pitch {
+    pub glide_mode : GlideMode,
+    /// seconds, or seconds per octave; zero switches portamento off
+    pub glide_time : FP,
+    /// semitones up and down for a full scale bend
+    pub bend_range : u8,
+
+    note : Ramp,
+    bend : Ramp,
+    bend_value : i32,
+    has_note : bool,
+}
+
+impl Default for Pitch {
+    fn default() -> Pitch {
+        Pitch::new()
+    }
+}
+
+impl Pitch {
+    pub fn new() -> Pitch {
+        Pitch {
+            glide_mode : GlideMode::ConstantTime,
+            glide_time : FP_ZERO,
+            bend_range : 2,
+
+            note : Ramp::new(FP_ZERO),
+            bend : Ramp::new(FP_ZERO),
+            bend_value : 0,
+            has_note : false,
+        }
+    }
+
+    /// jump to `flog2` without portamento
+    pub fn set_note(&mut self, flog2 : FP) {
+        self.note.set(flog2);
+        self.has_note = true;
+    }
+
+    /// move to `flog2`, gliding from the current pitch if portamento is on
+    pub fn glide_to(&mut self, flog2 : FP) {
+        if !self.has_note || self.glide_time <= FP_ZERO {
+            self.set_note(flog2);
+            return;
+        }
+
+        let time = match self.glide_mode {
+            GlideMode::ConstantTime => self.glide_time.repr as i64,
+            GlideMode::ConstantRate => {
+                let octaves = (flog2 - self.note.value()).repr.abs() as i64;
+                (self.glide_time.repr as i64 * octaves) >> 16
+            }
+        };
+        let samples = (time * SAMPLE_FREQ as i64) >> 16;
+        self.note.ramp_to(flog2, samples.clamp(0, u32::MAX as i64) as u32);
+    }
+
+    /// `value` is a centered MIDI pitch bend, -8192..8191
+    pub fn set_bend(&mut self, value : i32) {
+        self.bend_value = value.clamp(-BEND_MAX, BEND_MAX - 1);
+        let offset = (self.bend_value as i64 * self.bend_range as i64 * FP_ONE.repr as i64)
+            / (BEND_MAX as i64 * SEMITONES);
+        self.bend.ramp_to(FP::raw(offset as i32), BEND_SAMPLES);
+    }
+
+    pub fn bend(&self) -> i32 {
+        self.bend_value
+    }
+
+    /// the note pitch without bend, as it is at the moment
+    pub fn note(&self) -> FP {
+        self.note.value()
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.note.is_active()
+    }
+
+    /// advance one sample, returns the log2 frequency to play
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> FP {
+        self.note.next() + self.bend.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEMITONE : i32 = 0x1_0000 / 12;
+
+    #[test]
+    fn test_glide() {
+        let a3 = FP::from(220.0f64.log2());
+        let a4 = a3 + FP_ONE;
+
+        let mut pitch = Pitch::new();
+        pitch.glide_time = FP::from(0.1);
+        pitch.glide_to(a3); // first note does not glide
+        assert_eq!(pitch.next(), a3);
+
+        // constant time: 0.1s whatever the interval
+        pitch.glide_to(a4);
+        let mut last = a3;
+        for _ in 0..4799 {
+            let p = pitch.next();
+            assert!(p > last && (p - last).repr <= 14, "no steps during glide");
+            last = p;
+        }
+        assert_eq!(pitch.next(), a4);
+        assert!(!pitch.is_gliding());
+
+        // constant rate: 0.1s per octave, so half an octave takes 0.05s
+        pitch.glide_mode = GlideMode::ConstantRate;
+        pitch.glide_to(a4 - FP::raw(SEMITONE * 6));
+        let mut samples = 0;
+        while pitch.is_gliding() {
+            pitch.next();
+            samples += 1;
+        }
+        assert!((2395..=2405).contains(&samples));
+        assert_eq!(pitch.note(), a4 - FP::raw(SEMITONE * 6));
+    }
+
+    #[test]
+    fn test_bend() {
+        let a3 = FP::from(220.0f64.log2());
+
+        let mut pitch = Pitch::new();
+        pitch.set_note(a3);
+        pitch.bend_range = 12;
+        pitch.set_bend(4096);
+        let mut last = a3;
+        for _ in 0..BEND_SAMPLES {
+            let p = pitch.next();
+            assert!(p >= last);
+            last = p;
+        }
+        assert_eq!(last, a3 + FP::raw(0x8000)); // half an octave up
+
+        pitch.bend_range = 2;
+        pitch.set_bend(-BEND_MAX);
+        for _ in 0..BEND_SAMPLES {
+            pitch.next();
+        }
+        assert_eq!(pitch.next(), a3 - FP::raw(SEMITONE * 2));
+    }
+}
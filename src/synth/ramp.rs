@@ -0,0 +1,105 @@
+//! ramp
+//!
+//! linear ramp between FP values, advanced once per sample.
+//! The value is kept with 16 extra fraction bits, so that even slow ramps
+//! over small intervals move on every sample instead of in steps.
+
+use crate::fp::*;
+
+const EXTRA_BITS : usize = 16;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ramp {
+    value : i64,
+    step : i64,
+    target : FP,
+    remaining : u32,
+}
+
+impl Ramp {
+    pub fn new(value : FP) -> Ramp {
+        Ramp {
+            value : (value.repr as i64) << EXTRA_BITS,
+            step : 0,
+            target : value,
+            remaining : 0,
+        }
+    }
+
+    /// jump to `value` immediately
+    pub fn set(&mut self, value : FP) {
+        *self = Ramp::new(value);
+    }
+
+    /// move linearly to `target` in `samples` steps
+    pub fn ramp_to(&mut self, target : FP, samples : u32) {
+        if samples == 0 {
+            self.set(target);
+            return;
+        }
+        let distance = ((target.repr as i64) << EXTRA_BITS) - self.value;
+        self.step = distance / samples as i64;
+        self.target = target;
+        self.remaining = samples;
+    }
+
+    pub fn target(&self) -> FP {
+        self.target
+    }
+
+    pub fn value(&self) -> FP {
+        FP::raw((self.value >> EXTRA_BITS) as i32)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.remaining > 0
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> FP {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                // land exactly on target, whatever the rounding of step
+                self.value = (self.target.repr as i64) << EXTRA_BITS;
+            } else {
+                self.value += self.step;
+            }
+        }
+        self.value()
+    }
+}
+
+/// follows a parameter that is set directly, ramping to every new value.
+/// While the parameter stays put the ramp is bypassed.
+#[derive(Debug, Copy, Clone)]
+pub struct Smoother {
+    ramp : Ramp,
+}
+
+impl Smoother {
+    pub fn new(value : FP) -> Smoother {
+        Smoother { ramp : Ramp::new(value) }
+    }
+
+    /// advance one sample towards `target`, a change of target takes `samples`
+    pub fn next(&mut self, target : FP, samples : u32) -> FP {
+        if target != self.ramp.target() {
+            self.ramp.ramp_to(target, samples);
+        }
+        if self.ramp.is_active() {
+            self.ramp.next()
+        } else {
+            target
+        }
+    }
+
+    /// jump to `target`
+    pub fn settle(&mut self, target : FP) {
+        self.ramp.set(target);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.ramp.is_active()
+    }
+}
@@ -0,0 +1,281 @@
+//! random
+//!
+//! patch randomizer: playable voices with a random algorithm, waveforms,
+//! frequency ratios, levels and envelopes. `Constraints` lock parameters,
+//! keep carriers audible and bound the modulation depth; the generator is
+//! seeded, so a seed always gives the same voice.
+
+use super::param::{OpParam, Param};
+use super::voice::{Voice, ALGORITHM_COUNT};
+use super::wave_generator::WAVEFORMS;
+
+/// SplitMix64: small, fast and the same on every platform
+#[derive(Debug, Copy, Clone)]
+pub struct Rng {
+    state : u64,
+}
+
+impl Rng {
+    pub fn new(seed : u64) -> Rng {
+        Rng { state : seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in 0..1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// uniform in `min..=max`
+    pub fn uniform(&mut self, (min, max) : (f32, f32)) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// uniform in the logarithm, for rates; `min` must be positive
+    pub fn log_uniform(&mut self, (min, max) : (f32, f32)) -> f32 {
+        min * (max / min).powf(self.next_f32())
+    }
+
+    /// normally distributed with mean 0 and deviation 1, by Box-Muller
+    pub fn gaussian(&mut self) -> f32 {
+        let u = 1.0 - self.next_f32();
+        let v = self.next_f32();
+        (-2.0 * u.ln()).sqrt() * (2.0 * core::f32::consts::PI * v).cos()
+    }
+
+    /// uniform in `0..n`
+    pub fn below(&mut self, n : usize) -> usize {
+        (((self.next_u64() >> 32) * n as u64) >> 32) as usize
+    }
+
+    /// uniform among `items`, none if there are none; draws either way
+    pub fn choose<T : Copy>(&mut self, items : &[T]) -> Option<T> {
+        items.get(self.below(items.len())).copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraints {
+    /// parameters that keep their value in the base voice
+    pub locked : Vec<Param>,
+    /// algorithms to pick from, by index; indices of no algorithm are left
+    /// out, and the base voice's routing kept when none are left
+    pub algorithms : Vec<usize>,
+    /// waveforms to pick from, by index into `WAVEFORMS`; the base voice's
+    /// waveforms when empty
+    pub waveforms : Vec<usize>,
+    /// frequency ratios to pick from; any tune in range when empty
+    pub ratios : Vec<f32>,
+    /// total level of operators that are heard
+    pub carrier_level : (u8, u8),
+    /// total level of modulators, which bounds the modulation index
+    pub modulator_level : (u8, u8),
+    pub feedback_level : (u8, u8),
+    /// envelope rates, drawn uniformly in the logarithm
+    pub attack_rate : (f32, f32),
+    pub decay_rate : (f32, f32),
+    pub release_rate : (f32, f32),
+    pub sustain_level : (f32, f32),
+}
+
+impl Default for Constraints {
+    fn default() -> Constraints {
+        Constraints {
+            locked : Vec::new(),
+            algorithms : (0..ALGORITHM_COUNT).collect(),
+            waveforms : (0..WAVEFORMS.len()).collect(),
+            ratios : vec![0.5, 1.0, 1.0, 2.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0],
+            carrier_level : (160, 255),
+            modulator_level : (0, 200),
+            feedback_level : (0, 128),
+            attack_rate : (0.001, 1.0),
+            decay_rate : (0.0001, 0.05),
+            release_rate : (0.0002, 0.05),
+            sustain_level : (0.2, 1.0),
+        }
+    }
+}
+
+impl Constraints {
+    /// also lock all parameters of operator `op`
+    pub fn lock_operator(&mut self, op : usize) {
+        self.locked.extend(Param::all().filter(|p| matches!(p, Param::Op(o, _) if *o == op)));
+    }
+
+    fn is_locked(&self, param : Param) -> bool {
+        self.locked.contains(&param)
+    }
+}
+
+pub struct Randomizer {
+    pub constraints : Constraints,
+    rng : Rng,
+}
+
+impl Randomizer {
+    pub fn new(seed : u64) -> Randomizer {
+        Randomizer::with_constraints(seed, Constraints::default())
+    }
+
+    pub fn with_constraints(seed : u64, constraints : Constraints) -> Randomizer {
+        Randomizer { constraints, rng : Rng::new(seed) }
+    }
+
+    /// the next random voice; locked parameters, the glide and the smoothing
+    /// are those of `base`
+    pub fn voice(&mut self, base : &Voice) -> Voice {
+        let c = &self.constraints;
+        let rng = &mut self.rng;
+        let mut voice = *base;
+
+        // the carriers follow from the algorithm
+        let algorithms : Vec<usize> = c.algorithms.iter().copied()
+            .filter(|&algorithm| algorithm < ALGORITHM_COUNT).collect();
+        let algorithm = rng.choose(&algorithms);
+        if let Some(algorithm) = algorithm.filter(|_| !c.is_locked(Param::Algorithm)) {
+            voice.set_algorithm(algorithm);
+        }
+        let carriers = voice.carriers();
+
+        for (op, carrier) in carriers.into_iter().enumerate() {
+            let level = if carrier { c.carrier_level } else { c.modulator_level };
+            let tune = match rng.choose(&c.ratios) {
+                Some(ratio) => ratio.log2(),
+                None => rng.uniform(Param::Op(op, OpParam::Tune).range()),
+            };
+            let waveform = match rng.choose(&c.waveforms) {
+                Some(waveform) => waveform as f32,
+                None => Param::Op(op, OpParam::WaveForm).get(base),
+            };
+            let values = [
+                (OpParam::WaveForm, waveform),
+                (OpParam::TotalLevel, rng.uniform((level.0 as f32, level.1 as f32))),
+                (OpParam::FeedbackLevel, rng.uniform((c.feedback_level.0 as f32, c.feedback_level.1 as f32))),
+                (OpParam::Tune, tune),
+                (OpParam::AttackRate, rng.log_uniform(c.attack_rate)),
+                (OpParam::DecayRate, rng.log_uniform(c.decay_rate)),
+                (OpParam::SustainLevel, rng.uniform(c.sustain_level)),
+                (OpParam::ReleaseRate, rng.log_uniform(c.release_rate)),
+                (OpParam::IsSustained, rng.below(4).min(1) as f32),
+            ];
+            // draws happen for locked parameters too, so that locking one
+            // leaves the others as they were for the seed
+            for (p, value) in values {
+                if !c.is_locked(Param::Op(op, p)) {
+                    Param::Op(op, p).set(&mut voice, value);
+                }
+            }
+        }
+        voice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(voice : Voice) -> f32 {
+        let mut pool = crate::synth::voice_pool::VoicePool::new(voice);
+        pool.note_on(57, 100);
+        (0..9600).map(|_| pool.get_sample().abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_seed() {
+        let base = Voice::new();
+        let text = |voice : &Voice| crate::synth::patch::to_text(voice);
+        let first = Randomizer::new(7).voice(&base);
+        assert_eq!(text(&first), text(&Randomizer::new(7).voice(&base)));
+        assert_ne!(text(&first), text(&Randomizer::new(8).voice(&base)));
+
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&rng.next_f32()));
+            assert!(rng.below(3) < 3);
+        }
+    }
+
+    #[test]
+    fn test_constraints() {
+        let mut base = Voice::new();
+        base.set_algorithm(4);
+        base.operators[1].phase_gen.tune = crate::fp::FP::from(0.25);
+
+        let mut constraints = Constraints {
+            locked : vec![Param::Algorithm],
+            ratios : vec![1.0, 2.0],
+            modulator_level : (10, 20),
+            ..Constraints::default()
+        };
+        constraints.lock_operator(1);
+        let mut unlocked = Randomizer::new(3);
+        let mut randomizer = Randomizer::with_constraints(3, constraints);
+
+        for _ in 0..100 {
+            let free = unlocked.voice(&base);
+            let voice = randomizer.voice(&base);
+            assert_eq!(voice.algorithm(), Some(4));
+            assert_eq!(Param::Op(1, OpParam::Tune).get(&voice), 0.25);
+            // locks do not change the draws for other parameters
+            assert_eq!(voice.operators[2].env_gen.decay_rate, free.operators[2].env_gen.decay_rate);
+
+            for (op, carrier) in voice.carriers().into_iter().enumerate() {
+                if op == 1 {
+                    continue;
+                }
+                let level = voice.operators[op].total_level;
+                assert!(if carrier { level >= 160 } else { (10..=20).contains(&level) });
+                assert!([0.0, 1.0].contains(&Param::Op(op, OpParam::Tune).get(&voice)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_choices() {
+        let mut base = Voice::new();
+        base.set_algorithm(2);
+        base.operators[0].wave_gen.waveform = WAVEFORMS[1];
+        let constraints = Constraints {
+            algorithms : Vec::new(),
+            waveforms : Vec::new(),
+            ratios : Vec::new(),
+            ..Constraints::default()
+        };
+        let voice = Randomizer::with_constraints(5, constraints).voice(&base);
+        assert_eq!(voice.algorithm(), Some(2));
+        assert_eq!(voice.operators[0].wave_gen.waveform, WAVEFORMS[1]);
+    }
+
+    #[test]
+    fn test_bad_algorithms() {
+        let mut base = Voice::new();
+        base.set_algorithm(2);
+        let constraints = Constraints {
+            algorithms : vec![ALGORITHM_COUNT, 99],
+            ..Constraints::default()
+        };
+        let mut randomizer = Randomizer::with_constraints(5, constraints);
+        assert_eq!(randomizer.voice(&base).algorithm(), Some(2));
+
+        randomizer.constraints.algorithms.push(6);
+        for _ in 0..20 {
+            assert_eq!(randomizer.voice(&base).algorithm(), Some(6));
+        }
+    }
+
+    #[test]
+    fn test_audible() {
+        let mut randomizer = Randomizer::new(42);
+        for _ in 0..50 {
+            let voice = randomizer.voice(&Voice::new());
+            assert!(peak(voice) > 0.01, "{}", crate::synth::patch::to_text(&voice));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! resample
+//!
+//! the engine runs at `SAMPLE_FREQ`; hosts that run at another rate get the
+//! engine output linearly interpolated to their rate. At `SAMPLE_FREQ` the
+//! samples pass through untouched.
+//!
+//! There is no low-pass filter before the interpolation: at rates below
+//! `SAMPLE_FREQ`, whatever the engine plays above half the host rate
+//! aliases. Bright patches sound cleanest at `SAMPLE_FREQ` or above.
+
+use super::SAMPLE_FREQ;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Resampler {
+    /// engine samples per output sample
+    step : f64,
+    /// position between `prev` and `next`
+    phase : f64,
+    prev : f32,
+    next : f32,
+}
+
+impl Resampler {
+    pub fn new(rate : u32) -> Resampler {
+        Resampler {
+            step : SAMPLE_FREQ as f64 / rate.max(1) as f64,
+            phase : 0.0,
+            prev : 0.0,
+            next : 0.0,
+        }
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.step == 1.0
+    }
+
+    /// next output sample, taking engine samples from `source` as needed
+    pub fn next(&mut self, mut source : impl FnMut() -> f32) -> f32 {
+        if self.is_bypassed() {
+            return source();
+        }
+        self.phase += self.step;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.prev = self.next;
+            self.next = source();
+        }
+        self.prev + (self.next - self.prev) * self.phase as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample() {
+        let mut counter = 0.0;
+        let mut count = || { counter += 1.0; counter };
+
+        let mut same = Resampler::new(SAMPLE_FREQ);
+        assert!(same.is_bypassed());
+        let out : Vec<f32> = (0..4).map(|_| same.next(&mut count)).collect();
+        assert_eq!(out, [1.0, 2.0, 3.0, 4.0]);
+
+        // half the rate takes every other sample
+        let mut half = Resampler::new(SAMPLE_FREQ / 2);
+        let out : Vec<f32> = (0..4).map(|_| half.next(&mut count)).collect();
+        assert_eq!(out, [5.0, 7.0, 9.0, 11.0]);
+
+        // double the rate puts a sample halfway in between. The output
+        // trails the engine by one sample and starts from silence, so the
+        // first two samples are 0 and the third is halfway up to 13
+        let mut double = Resampler::new(SAMPLE_FREQ * 2);
+        let out : Vec<f32> = (0..6).map(|_| double.next(&mut count)).collect();
+        assert_eq!(out, [0.0, 0.0, 6.5, 13.0, 13.5, 14.0]);
+    }
+}
@@ -0,0 +1,532 @@
+//! resynth
+//!
+//! patch matching: a genetic algorithm searches the patch parameters for
+//! the voice whose rendering of one note comes closest to a recorded
+//! target. Closeness is a multi-resolution STFT distance, spectral
+//! convergence plus log magnitude difference summed over several FFT
+//! sizes, so that both the spectral envelope and its course over time
+//! count. Both signals are normalized to the same RMS first: the level of
+//! a recording says nothing about the patch.
+//!
+//! All of it runs offline on the CPU; each generation renders its
+//! candidates on all cores.
+
+use std::f32::consts::PI;
+use std::fmt;
+#[cfg(feature = "wav")]
+use std::path::Path;
+use std::thread;
+
+use super::param::{OpParam, Param, Unit};
+use super::random::{Constraints, Randomizer, Rng};
+use super::scheduler::render_notes;
+use super::voice::{Voice, ALGORITHM_COUNT};
+
+/// FFT sizes of the distance, in samples
+pub const FFT_SIZES : [usize; 3] = [2048, 512, 128];
+
+/// smallest envelope rate searched; rates are searched in the logarithm
+const MIN_RATE : f32 = 1e-5;
+
+/// keeps logarithms of silent bins finite
+const EPSILON : f32 = 1e-5;
+
+/// candidates picked at random for each parent, the fittest of which wins
+const TOURNAMENT : usize = 3;
+
+/// best candidates carried over unchanged into the next generation
+const ELITE : usize = 2;
+
+/// in-place radix-2 FFT; the length must be a power of two
+pub fn fft(re : &mut [f32], im : &mut [f32]) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n);
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let twiddles : Vec<(f32, f32)> = (0..n / 2)
+        .map(|k| (-2.0 * PI * k as f32 / n as f32).sin_cos())
+        .collect();
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// magnitude spectra of Hann windowed frames of `size` samples, a quarter
+/// frame apart, one after another; the end is padded with silence. The
+/// size must be a power of two of at least 4.
+pub fn stft(signal : &[f32], size : usize) -> Vec<f32> {
+    let hop = size / 4;
+    let window : Vec<f32> = (0..size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+        .collect();
+    let frames = signal.len().saturating_sub(1) / hop + 1;
+
+    let mut spectra = Vec::with_capacity(frames * (size / 2 + 1));
+    let (mut re, mut im) = (vec![0.0; size], vec![0.0; size]);
+    for frame in 0..frames {
+        for i in 0..size {
+            re[i] = signal.get(frame * hop + i).map_or(0.0, |s| s * window[i]);
+            im[i] = 0.0;
+        }
+        fft(&mut re, &mut im);
+        spectra.extend((0..=size / 2).map(|k| re[k].hypot(im[k])));
+    }
+    spectra
+}
+
+/// `signal` scaled to an RMS of 1, unless it is silent
+fn normalize(signal : &[f32]) -> Vec<f32> {
+    let rms = (signal.iter().map(|s| s * s).sum::<f32>() / signal.len().max(1) as f32).sqrt();
+    let gain = if rms > 0.0 { 1.0 / rms } else { 0.0 };
+    signal.iter().map(|s| s * gain).collect()
+}
+
+/// the spectra the distance compares, one per FFT size
+pub fn spectra(signal : &[f32], sizes : &[usize]) -> Vec<Vec<f32>> {
+    let signal = normalize(signal);
+    sizes.iter().map(|&size| stft(&signal, size)).collect()
+}
+
+/// distance of spectra as from `spectra` of signals of the same length
+pub fn spectral_distance(target : &[Vec<f32>], candidate : &[Vec<f32>]) -> f32 {
+    target.iter().zip(candidate).map(|(t, c)| {
+        let diff = t.iter().zip(c).map(|(t, c)| (t - c) * (t - c)).sum::<f32>().sqrt();
+        let norm = t.iter().map(|t| t * t).sum::<f32>().sqrt().max(EPSILON);
+        let log = t.iter().zip(c)
+            .map(|(t, c)| ((t + EPSILON).ln() - (c + EPSILON).ln()).abs())
+            .sum::<f32>() / t.len().max(1) as f32;
+        diff / norm + log
+    }).sum()
+}
+
+/// multi-resolution STFT distance of two signals of the same length
+pub fn stft_distance(target : &[f32], candidate : &[f32], sizes : &[usize]) -> f32 {
+    spectral_distance(&spectra(target, sizes), &spectra(candidate, sizes))
+}
+
+#[derive(Debug)]
+pub enum ResynthError {
+    BadFftSize(usize),
+    NoFftSizes,
+    EmptyTarget,
+    BadSampleRate,
+}
+
+impl fmt::Display for ResynthError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResynthError::BadFftSize(size) => write!(f, "FFT size {size} is not a power of two of at least 4"),
+            ResynthError::NoFftSizes       => write!(f, "no FFT sizes"),
+            ResynthError::EmptyTarget      => write!(f, "target has no samples"),
+            ResynthError::BadSampleRate    => write!(f, "sample rate is 0"),
+        }
+    }
+}
+
+impl std::error::Error for ResynthError {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub key : u8,
+    pub velocity : u8,
+    /// seconds the key is held; the rest of the target is its release
+    pub hold : f64,
+    pub population : usize,
+    pub generations : usize,
+    /// chance of a gene to mutate in each offspring
+    pub mutation_rate : f32,
+    /// deviation of a mutation, as a fraction of the parameter range
+    pub mutation_width : f32,
+    pub seed : u64,
+    pub fft_sizes : Vec<usize>,
+    /// locked parameters, and the ranges of the random first generation
+    pub constraints : Constraints,
+    /// threads to render on; all cores when 0
+    pub threads : usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            key : 60,
+            velocity : 100,
+            hold : f64::INFINITY,
+            population : 48,
+            generations : 40,
+            mutation_rate : 0.1,
+            mutation_width : 0.1,
+            seed : 0,
+            fft_sizes : FFT_SIZES.to_vec(),
+            constraints : Constraints::default(),
+            threads : 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub voice : Voice,
+    pub distance : f32,
+    /// the searched parameters, each scaled to 0..1
+    genome : Vec<f32>,
+}
+
+pub struct Resynth {
+    options : Options,
+    base : Voice,
+    genes : Vec<Param>,
+    target : Vec<Vec<f32>>,
+    length : f64,
+    sample_rate : u32,
+    rng : Rng,
+    generation : usize,
+    /// sorted, best first
+    population : Vec<Candidate>,
+}
+
+impl Resynth {
+    /// a search for `target`, recorded at `sample_rate`. Parameters that
+    /// are not searched, the locked ones, glide, bend range and smoothing,
+    /// are those of `base`. The routing is searched among the algorithm
+    /// presets only.
+    pub fn new(target : &[f32], sample_rate : u32, base : Voice, options : Options) -> Result<Resynth, ResynthError> {
+        if let Some(&size) = options.fft_sizes.iter().find(|size| **size < 4 || !size.is_power_of_two()) {
+            return Err(ResynthError::BadFftSize(size));
+        }
+        if options.fft_sizes.is_empty() {
+            return Err(ResynthError::NoFftSizes);
+        }
+        if target.is_empty() {
+            return Err(ResynthError::EmptyTarget);
+        }
+        if sample_rate == 0 {
+            return Err(ResynthError::BadSampleRate);
+        }
+
+        let constraints = &options.constraints;
+        let genes = Param::all()
+            .filter(|p| match p {
+                Param::Algorithm => true,
+                Param::Op(_, op) => !matches!(op, OpParam::Smoothing | OpParam::Output),
+                _ => false,
+            })
+            .filter(|p| !constraints.locked.contains(p))
+            .collect();
+
+        let mut resynth = Resynth {
+            target : spectra(target, &options.fft_sizes),
+            length : target.len() as f64 / sample_rate as f64,
+            rng : Rng::new(options.seed),
+            generation : 0,
+            population : Vec::new(),
+            options,
+            base,
+            genes,
+            sample_rate,
+        };
+
+        // the first generation is random but playable
+        let mut randomizer = Randomizer::with_constraints(resynth.options.seed, resynth.options.constraints.clone());
+        let genomes = (0..resynth.options.population.max(ELITE + 1))
+            .map(|_| {
+                let voice = randomizer.voice(&base);
+                resynth.genes.iter().map(|p| encode(*p, p.get(&voice))).collect()
+            })
+            .collect();
+        resynth.population = resynth.evaluate(genomes);
+        Ok(resynth)
+    }
+
+    pub fn best(&self) -> &Candidate {
+        &self.population[0]
+    }
+
+    /// generations bred so far
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// breed the next generation
+    pub fn step(&mut self) {
+        let size = self.population.len();
+        let mut genomes : Vec<Vec<f32>> = self.population[..ELITE].iter()
+            .map(|c| c.genome.clone())
+            .collect();
+        while genomes.len() < size {
+            let (a, b) = (self.select(), self.select());
+            let child = a.iter().zip(&b)
+                .map(|(a, b)| {
+                    let gene = if self.rng.next_f32() < 0.5 { *a } else { *b };
+                    match self.rng.next_f32() < self.options.mutation_rate {
+                        true => (gene + self.options.mutation_width * self.rng.gaussian()).clamp(0.0, 1.0),
+                        false => gene,
+                    }
+                })
+                .collect();
+            genomes.push(child);
+        }
+
+        // the elite keep their distance
+        let mut population : Vec<Candidate> = self.population.drain(..ELITE).collect();
+        population.extend(self.evaluate(genomes.split_off(ELITE)));
+        population.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        self.population = population;
+        self.generation += 1;
+    }
+
+    /// run all generations, calling `progress` with the generation and the
+    /// best distance after each
+    pub fn run(mut self, mut progress : impl FnMut(usize, f32)) -> Candidate {
+        while self.generation < self.options.generations {
+            self.step();
+            progress(self.generation, self.best().distance);
+        }
+        self.population.swap_remove(0)
+    }
+
+    fn select(&mut self) -> Vec<f32> {
+        let best = (0..TOURNAMENT)
+            .map(|_| self.rng.below(self.population.len()))
+            .min()
+            .unwrap_or(0);
+        self.population[best].genome.clone()
+    }
+
+    fn voice(&self, genome : &[f32]) -> Voice {
+        let mut voice = self.base;
+        for (param, gene) in self.genes.iter().zip(genome) {
+            param.set(&mut voice, decode(*param, *gene));
+        }
+        voice
+    }
+
+    /// render and rate `genomes` on all threads, sorted best first
+    fn evaluate(&self, genomes : Vec<Vec<f32>>) -> Vec<Candidate> {
+        let threads = match self.options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let chunk = genomes.len().div_ceil(threads).max(1);
+        let mut candidates : Vec<Candidate> = thread::scope(|scope| {
+            let workers : Vec<_> = genomes.chunks(chunk)
+                .map(|genomes| scope.spawn(move || {
+                    genomes.iter().map(|genome| self.candidate(genome.clone())).collect::<Vec<_>>()
+                }))
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+        candidates.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        candidates
+    }
+
+    fn candidate(&self, genome : Vec<f32>) -> Candidate {
+        let voice = self.voice(&genome);
+        let note = (0.0, self.options.hold.min(self.length), self.options.key, self.options.velocity);
+        let rendered = render_notes(voice, &[note], self.length, self.sample_rate);
+        let distance = spectral_distance(&self.target, &spectra(&rendered, &self.options.fft_sizes));
+        Candidate { voice, distance, genome }
+    }
+}
+
+/// range of `param` that is searched: the algorithm is one of the presets,
+/// not "custom"
+fn range(param : Param) -> (f32, f32) {
+    match param {
+        Param::Algorithm => (0.0, (ALGORITHM_COUNT - 1) as f32),
+        _ => param.range(),
+    }
+}
+
+/// value of `param` for `gene` in 0..1
+fn decode(param : Param, gene : f32) -> f32 {
+    let (min, max) = range(param);
+    match param.unit() {
+        Unit::Rate => MIN_RATE * (max / MIN_RATE).powf(gene),
+        _ => min + (max - min) * gene,
+    }
+}
+
+fn encode(param : Param, value : f32) -> f32 {
+    let (min, max) = range(param);
+    let gene = match param.unit() {
+        Unit::Rate => (value.max(MIN_RATE) / MIN_RATE).ln() / (max / MIN_RATE).ln(),
+        _ => (value - min) / (max - min),
+    };
+    gene.clamp(0.0, 1.0)
+}
+
+/// the samples of a WAV file, channels mixed down, and its sample rate
+#[cfg(feature = "wav")]
+pub fn load_wav<P : AsRef<Path>>(path : P) -> Result<(Vec<f32>, u32), hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples : Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp::*;
+    use crate::synth::SAMPLE_FREQ;
+
+    fn target_voice() -> Voice {
+        let mut voice = Voice::new();
+        voice.operators[2].total_level = 90;
+        voice.operators[2].phase_gen.tune = FP::from(1);
+        voice.operators[2].env_gen.attack_rate = FP::from(1);
+        voice.operators[2].env_gen.decay_rate = FP::from(0.01);
+        voice.operators[3].env_gen.attack_rate = FP::from(0.2);
+        voice.set_algorithm(0);
+        voice
+    }
+
+    #[test]
+    fn test_fft() {
+        const N : usize = 16;
+        let signal : Vec<f32> = (0..N).map(|i| ((i * 7 % 5) as f32 - 2.0) * 0.3).collect();
+        let (mut re, mut im) = (signal.clone(), vec![0.0; N]);
+        fft(&mut re, &mut im);
+        for k in 0..N {
+            let (mut dft_re, mut dft_im) = (0.0, 0.0);
+            for (i, s) in signal.iter().enumerate() {
+                let angle = -2.0 * PI * (k * i) as f32 / N as f32;
+                dft_re += s * angle.cos();
+                dft_im += s * angle.sin();
+            }
+            assert!((re[k] - dft_re).abs() < 1e-4 && (im[k] - dft_im).abs() < 1e-4, "bin {k}");
+        }
+        assert_eq!(stft(&signal, 8).len(), 8 * 5);
+    }
+
+    #[test]
+    fn test_distance() {
+        let render = |voice, key| render_notes(voice, &[(0.0, 0.1, key, 100)], 0.15, SAMPLE_FREQ);
+        let target = render(target_voice(), 60);
+        let louder : Vec<f32> = target.iter().map(|s| s * 3.0).collect();
+        let mut brighter = target_voice();
+        brighter.operators[2].total_level = 100;
+
+        assert_eq!(stft_distance(&target, &target, &FFT_SIZES), 0.0);
+        // up to rounding
+        assert!(stft_distance(&target, &louder, &FFT_SIZES) < 0.01);
+        let near = stft_distance(&target, &render(brighter, 60), &FFT_SIZES);
+        let far = stft_distance(&target, &render(target_voice(), 67), &FFT_SIZES);
+        assert!(near > 0.1 && near < far, "{near} {far}");
+        assert!(stft_distance(&target, &vec![0.0; target.len()], &FFT_SIZES) > far);
+    }
+
+    #[test]
+    fn test_genes() {
+        for param in Param::all() {
+            let (min, max) = range(param);
+            for value in [min, max, min + (max - min) * 0.3] {
+                let value = value.max(if matches!(param.unit(), Unit::Rate) { MIN_RATE } else { min });
+                assert!((decode(param, encode(param, value)) - value).abs() <= (max - min) * 1e-4,
+                    "{param:?} {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let target = render_notes(target_voice(), &[(0.0, 0.04, 60, 100)], 0.05, 24000);
+        let options = Options {
+            population : 12,
+            generations : 5,
+            hold : 0.04,
+            fft_sizes : vec![512, 128],
+            constraints : Constraints { locked : vec![Param::Algorithm], ..Constraints::default() },
+            ..Options::default()
+        };
+        let search = |threads| {
+            let mut resynth = Resynth::new(&target, 24000, target_voice(), Options { threads, ..options.clone() }).unwrap();
+            let first = resynth.best().distance;
+            let mut last = first;
+            for _ in 0..options.generations {
+                resynth.step();
+                assert!(resynth.best().distance <= last);
+                last = resynth.best().distance;
+            }
+            assert_eq!(resynth.generation(), options.generations);
+            assert!(last < first);
+            assert_eq!(resynth.best().voice.algorithm(), Some(0));
+            last
+        };
+        // the result does not depend on the threads it was found with
+        assert_eq!(search(1), search(3));
+
+        let mut progress = Vec::new();
+        let best = Resynth::new(&target, 24000, target_voice(), options.clone()).unwrap()
+            .run(|generation, distance| progress.push((generation, distance)));
+        assert_eq!(progress.len(), 5);
+        assert_eq!(progress[4], (5, best.distance));
+
+        let bad = |fft_sizes| Resynth::new(&target, 24000, target_voice(), Options { fft_sizes, ..options.clone() }).err();
+        assert!(matches!(bad(vec![512, 2]), Some(ResynthError::BadFftSize(2))));
+        assert!(matches!(bad(vec![100]), Some(ResynthError::BadFftSize(100))));
+        assert!(matches!(bad(Vec::new()), Some(ResynthError::NoFftSizes)));
+        assert!(matches!(Resynth::new(&[], 24000, target_voice(), options.clone()).err(), Some(ResynthError::EmptyTarget)));
+        assert!(matches!(Resynth::new(&target, 0, target_voice(), options).err(), Some(ResynthError::BadSampleRate)));
+    }
+
+    #[cfg(feature = "wav")]
+    #[test]
+    fn test_load_wav() {
+        let path = std::env::temp_dir().join("beriq_fm_test_load_wav.wav");
+        let spec = hound::WavSpec {
+            channels : 2,
+            sample_rate : 22050,
+            bits_per_sample : 16,
+            sample_format : hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [16384i16, 0, -32768, -32768] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (samples, rate) = load_wav(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rate, 22050);
+        assert_eq!(samples, [0.25, -1.0]);
+        assert!(load_wav("no/such.wav").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! scheduler
+//!
+//! renders a voice pool along a timeline of events stamped in samples,
+//! for sequenced playback and offline rendering. Blocks are split exactly
+//! at event times, so the output does not depend on the block size it is
+//! rendered in.
+#[cfg(feature = "rodio")]
+use std::time::Duration;
+#[cfg(feature = "rodio")]
+use rodio::source::Source;
+
+use super::SAMPLE_FREQ;
+use super::control::Event;
+use super::resample::Resampler;
+use super::voice::Voice;
+use super::voice_pool::VoicePool;
+
+/// start and duration in seconds, key, velocity
+pub type Note = (f64, f64, u8, u8);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimedEvent {
+    /// samples from the start of the timeline
+    pub time : u64,
+    pub event : Event,
+}
+
+/// events ordered by time; events at the same time keep the order in which
+/// they were added
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    events : Vec<TimedEvent>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline { events : Vec::new() }
+    }
+
+    pub fn add(&mut self, time : u64, event : Event) {
+        let idx = self.events.partition_point(|e| e.time <= time);
+        self.events.insert(idx, TimedEvent { time, event });
+    }
+
+    pub fn events(&self) -> &[TimedEvent] {
+        &self.events
+    }
+
+    /// time of the last event
+    pub fn end(&self) -> u64 {
+        self.events.last().map_or(0, |e| e.time)
+    }
+}
+
+pub struct Sequencer {
+    pub pool : VoicePool,
+    timeline : Timeline,
+    position : u64,
+    next : usize,
+}
+
+impl Sequencer {
+    pub fn new(pool : VoicePool, timeline : Timeline) -> Sequencer {
+        Sequencer { pool, timeline, position : 0, next : 0 }
+    }
+
+    /// samples rendered so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// add an event while playing; one that is due already plays at once
+    pub fn add(&mut self, time : u64, event : Event) {
+        self.timeline.add(time.max(self.position), event);
+    }
+
+    /// render the next `out.len()` samples
+    pub fn render(&mut self, out : &mut [f32]) {
+        let mut done = 0;
+        while done < out.len() {
+            self.apply_due();
+
+            // run up to the next event or the end of the block
+            let left = (out.len() - done) as u64;
+            let until = self.timeline.events.get(self.next)
+                .map_or(left, |e| (e.time - self.position).min(left)) as usize;
+
+            for sample in &mut out[done..done + until] {
+                *sample = self.pool.get_sample();
+            }
+            done += until;
+            self.position += until as u64;
+        }
+    }
+
+    pub fn render_to_vec(&mut self, samples : usize) -> Vec<f32> {
+        let mut out = vec![0.0; samples];
+        self.render(&mut out);
+        out
+    }
+
+    pub fn get_sample(&mut self) -> f32 {
+        self.apply_due();
+        self.position += 1;
+        self.pool.get_sample()
+    }
+
+    fn apply_due(&mut self) {
+        while let Some(timed) = self.timeline.events.get(self.next) {
+            if timed.time > self.position {
+                break;
+            }
+            timed.event.apply(&mut self.pool);
+            self.next += 1;
+        }
+    }
+}
+
+/// `length` seconds of a voice pool with `voice` playing `notes`, at
+/// `sample_rate`, for offline rendering
+pub fn render_notes(voice : Voice, notes : &[Note], length : f64, sample_rate : u32) -> Vec<f32> {
+    let at = |seconds : f64| (seconds.max(0.0) * SAMPLE_FREQ as f64).round() as u64;
+    let mut timeline = Timeline::new();
+    for &(start, duration, key, velocity) in notes {
+        timeline.add(at(start), Event::NoteOn { key, velocity });
+        timeline.add(at(start + duration), Event::NoteOff { key });
+    }
+
+    let mut sequencer = Sequencer::new(VoicePool::new(voice), timeline);
+    let mut resampler = Resampler::new(sample_rate);
+    let samples = (length.max(0.0) * sample_rate as f64).round() as usize;
+    (0..samples).map(|_| resampler.next(|| sequencer.get_sample())).collect()
+}
+
+impl Iterator for Sequencer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        return Some(self.get_sample());
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Source for Sequencer {
+    fn channels(&self) -> u16 {
+        return 1;
+    }
+
+     fn sample_rate(&self) -> u32 {
+        return super::SAMPLE_FREQ;
+     }
+
+     fn current_frame_len(&self) -> Option<usize> {
+        return None;
+     }
+
+     fn total_duration(&self) -> Option<Duration> {
+        return None;
+     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::param::*;
+
+    fn timeline() -> Timeline {
+        let mut timeline = Timeline::new();
+        timeline.add(4000, Event::NoteOff { key : 60 });
+        timeline.add(17, Event::NoteOn { key : 60, velocity : 100 });
+        timeline.add(1001, Event::SetParam { param : Param::Op(2, OpParam::TotalLevel), value : 200.0 });
+        timeline.add(1001, Event::SetAlgorithm { algorithm : 5 });
+        timeline.add(2500, Event::NoteOn { key : 67, velocity : 80 });
+        timeline.add(2500, Event::PitchBend { value : 4000 });
+        timeline
+    }
+
+    fn sequencer() -> Sequencer {
+        let mut patch = Voice::new();
+        patch.operators[2].total_level = 64;
+        Sequencer::new(VoicePool::new(patch), timeline())
+    }
+
+    #[test]
+    fn test_order() {
+        let timeline = timeline();
+        let times : Vec<u64> = timeline.events().iter().map(|e| e.time).collect();
+        assert_eq!(times, [17, 1001, 1001, 2500, 2500, 4000]);
+        assert_eq!(timeline.events()[1].event,
+            Event::SetParam { param : Param::Op(2, OpParam::TotalLevel), value : 200.0 });
+        assert_eq!(timeline.end(), 4000);
+    }
+
+    #[test]
+    fn test_block_size() {
+        const LENGTH : usize = 6000;
+        let reference = sequencer().render_to_vec(LENGTH);
+        assert!(reference[..17].iter().all(|s| *s == 0.0));
+        assert!(reference[17..].iter().any(|s| *s != 0.0));
+
+        for block in [1, 7, 64, 1000, 4096] {
+            let mut seq = sequencer();
+            let mut out = Vec::new();
+            while out.len() < LENGTH {
+                let mut buffer = vec![0.0; block.min(LENGTH - out.len())];
+                seq.render(&mut buffer);
+                out.extend(buffer);
+            }
+            assert_eq!(out, reference, "block size {block}");
+        }
+
+        let mut seq = sequencer();
+        let samples : Vec<f32> = (0..LENGTH).map(|_| seq.get_sample()).collect();
+        assert_eq!(samples, reference);
+        assert!(seq.pool.voices().all(|voice| voice.algorithm() == Some(5)));
+    }
+
+    #[test]
+    fn test_add() {
+        let mut seq = sequencer();
+        let mut reference = sequencer();
+        reference.add(3000, Event::NoteOn { key : 72, velocity : 100 });
+        let reference = reference.render_to_vec(4000);
+
+        let mut out = seq.render_to_vec(2000);
+        seq.add(3000, Event::NoteOn { key : 72, velocity : 100 });
+        out.extend(seq.render_to_vec(2000));
+        assert_eq!(out, reference);
+
+        // an event in the past plays now
+        seq.add(10, Event::SetAlgorithm { algorithm : 2 });
+        assert_eq!(seq.timeline().events().last().unwrap().time, 4000);
+        seq.render_to_vec(1);
+        assert!(seq.pool.voices().all(|voice| voice.algorithm() == Some(2)));
+    }
+
+    #[test]
+    fn test_render_notes() {
+        let mut voice = Voice::new();
+        voice.operators[3].env_gen.attack_rate = crate::fp::FP::from(1);
+
+        let notes = [(0.0, 0.1, 60, 100), (0.05, 0.1, 64, 100)];
+        let samples = render_notes(voice, &notes, 0.25, SAMPLE_FREQ);
+        assert_eq!(samples.len(), 12000);
+        assert!(samples.iter().any(|s| s.abs() > 0.1));
+
+        // the same notes as the sequencer plays them
+        let mut timeline = Timeline::new();
+        timeline.add(0, Event::NoteOn { key : 60, velocity : 100 });
+        timeline.add(2400, Event::NoteOn { key : 64, velocity : 100 });
+        timeline.add(4800, Event::NoteOff { key : 60 });
+        timeline.add(7200, Event::NoteOff { key : 64 });
+        let reference = Sequencer::new(VoicePool::new(voice), timeline).render_to_vec(12000);
+        assert_eq!(samples, reference);
+
+        assert_eq!(render_notes(voice, &notes, 0.25, 44100).len(), 11025);
+        assert!(render_notes(voice, &[], 0.1, SAMPLE_FREQ).iter().all(|s| *s == 0.0));
+    }
+}
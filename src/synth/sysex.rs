@@ -0,0 +1,630 @@
+//! sysex
+//!
+//! DX7 voice data as system exclusive messages: single voices (VCED, 155
+//! parameter bytes) and banks of 32 voices packed into 128 bytes each
+//! (VMEM), as in the many `.syx` files around.
+//!
+//! ```text
+//! F0 43 0n 00 01 1B <155 bytes> checksum F7    one voice
+//! F0 43 0n 09 20 00 <4096 bytes> checksum F7   32 voices
+//! ```
+//!
+//! `Dx7Patch` keeps every parameter of a DX7 voice, so messages read and
+//! written again are the same. `to_voice` makes a `Dx7Voice` of what the
+//! engine has: the algorithm, feedback, output levels, frequency ratios
+//! and envelopes. Velocity sensitivity, keyboard scaling, the pitch
+//! envelope and the LFO stay in the patch.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::fp::*;
+
+use super::dx7::{Dx7Voice, DX7_OPERATORS};
+
+pub const BANK_VOICES : usize = 32;
+
+const SYSEX_START : u8 = 0xF0;
+const SYSEX_END : u8 = 0xF7;
+const YAMAHA : u8 = 0x43;
+const FORMAT_VCED : u8 = 0;
+const FORMAT_VMEM : u8 = 9;
+
+/// parameter bytes of a voice, unpacked
+const VCED_SIZE : usize = 155;
+/// bytes of a voice packed into a bank
+const VMEM_SIZE : usize = 128;
+/// bytes of an operator, unpacked and packed
+const OP_VCED_SIZE : usize = 21;
+const OP_VMEM_SIZE : usize = 17;
+/// start of the voice parameters after the operators
+const VOICE_OFFSET : usize = DX7_OPERATORS * OP_VCED_SIZE;
+const NAME_SIZE : usize = 10;
+
+/// highest value and name of each operator parameter
+const OP_PARAMS : [(u8, &str); OP_VCED_SIZE] = [
+    (99, "EG rate 1"), (99, "EG rate 2"), (99, "EG rate 3"), (99, "EG rate 4"),
+    (99, "EG level 1"), (99, "EG level 2"), (99, "EG level 3"), (99, "EG level 4"),
+    (99, "break point"), (99, "left depth"), (99, "right depth"), (3, "left curve"), (3, "right curve"),
+    (7, "rate scaling"), (3, "amp mod sensitivity"), (7, "velocity sensitivity"), (99, "output level"),
+    (1, "oscillator mode"), (31, "frequency coarse"), (99, "frequency fine"), (14, "detune"),
+];
+
+/// highest value and name of each voice parameter but the name
+const VOICE_PARAMS : [(u8, &str); VCED_SIZE - VOICE_OFFSET - NAME_SIZE] = [
+    (99, "pitch EG rate 1"), (99, "pitch EG rate 2"), (99, "pitch EG rate 3"), (99, "pitch EG rate 4"),
+    (99, "pitch EG level 1"), (99, "pitch EG level 2"), (99, "pitch EG level 3"), (99, "pitch EG level 4"),
+    (31, "algorithm"), (7, "feedback"), (1, "oscillator key sync"), (99, "LFO speed"), (99, "LFO delay"),
+    (99, "LFO pitch depth"), (99, "LFO amp depth"), (1, "LFO key sync"), (5, "LFO waveform"),
+    (7, "pitch mod sensitivity"), (48, "transpose"),
+];
+
+#[derive(Debug)]
+pub enum SysexError {
+    Io(std::io::Error),
+    /// not a DX7 voice or bank message
+    Header,
+    /// length of a message that is too short or does not end where its
+    /// format says
+    Length(usize),
+    /// checksum in the message, checksum of its data
+    Checksum(u8, u8),
+    /// voice in the message (from 0), offset in its unpacked parameters,
+    /// value that is out of range
+    Range(usize, usize, u8),
+}
+
+impl fmt::Display for SysexError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SysexError::Io(e)                   => write!(f, "i/o error: {e}"),
+            SysexError::Header                  => write!(f, "not a DX7 voice or bank message"),
+            SysexError::Length(length)          => write!(f, "message of {length} bytes has the wrong length"),
+            SysexError::Checksum(stored, data)  => write!(f, "checksum is {stored:#04x}, data sums to {data:#04x}"),
+            SysexError::Range(voice, offset, value) => {
+                let (max, name) = param(*offset);
+                write!(f, "voice {}: {name} is {value}, above {max}", voice + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SysexError {}
+
+impl From<std::io::Error> for SysexError {
+    fn from(e : std::io::Error) -> Self {
+        SysexError::Io(e)
+    }
+}
+
+/// highest value and name of the parameter at `offset` of a voice
+fn param(offset : usize) -> (u8, String) {
+    if offset < VOICE_OFFSET {
+        let (max, name) = OP_PARAMS[offset % OP_VCED_SIZE];
+        // operators are stored from the sixth down
+        (max, format!("op{} {name}", DX7_OPERATORS - offset / OP_VCED_SIZE))
+    } else if offset < VOICE_OFFSET + VOICE_PARAMS.len() {
+        let (max, name) = VOICE_PARAMS[offset - VOICE_OFFSET];
+        (max, String::from(name))
+    } else {
+        (127, String::from("name"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dx7Operator {
+    pub eg_rates : [u8; 4],
+    pub eg_levels : [u8; 4],
+    /// keyboard level scaling
+    pub break_point : u8,
+    pub left_depth : u8,
+    pub right_depth : u8,
+    /// -LIN, -EXP, +EXP, +LIN
+    pub left_curve : u8,
+    pub right_curve : u8,
+    /// keyboard rate scaling
+    pub rate_scaling : u8,
+    pub amp_mod_sensitivity : u8,
+    pub velocity_sensitivity : u8,
+    pub output_level : u8,
+    /// fixed frequency rather than a ratio
+    pub fixed : bool,
+    pub coarse : u8,
+    pub fine : u8,
+    /// 7 is in tune
+    pub detune : u8,
+}
+
+/// every parameter of a DX7 voice, with the operators in the order of the
+/// engine: DX7 operator 6 first
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dx7Patch {
+    pub operators : [Dx7Operator; DX7_OPERATORS],
+    pub pitch_eg_rates : [u8; 4],
+    pub pitch_eg_levels : [u8; 4],
+    /// from 0
+    pub algorithm : u8,
+    pub feedback : u8,
+    pub osc_key_sync : bool,
+    pub lfo_speed : u8,
+    pub lfo_delay : u8,
+    pub lfo_pitch_depth : u8,
+    pub lfo_amp_depth : u8,
+    pub lfo_key_sync : bool,
+    /// triangle, saw down, saw up, square, sine, sample and hold
+    pub lfo_waveform : u8,
+    pub pitch_mod_sensitivity : u8,
+    /// semitones, 24 for none
+    pub transpose : u8,
+    pub name : [u8; NAME_SIZE],
+}
+
+impl Default for Dx7Patch {
+    /// the INIT VOICE of the DX7: operator 1 alone, at full level
+    fn default() -> Dx7Patch {
+        let op = Dx7Operator {
+            eg_rates : [99; 4],
+            eg_levels : [99, 99, 99, 0],
+            break_point : 39,
+            left_depth : 0,
+            right_depth : 0,
+            left_curve : 0,
+            right_curve : 0,
+            rate_scaling : 0,
+            amp_mod_sensitivity : 0,
+            velocity_sensitivity : 0,
+            output_level : 0,
+            fixed : false,
+            coarse : 1,
+            fine : 0,
+            detune : 7,
+        };
+        let mut operators = [op; DX7_OPERATORS];
+        operators[DX7_OPERATORS - 1].output_level = 99;
+        Dx7Patch {
+            operators,
+            pitch_eg_rates : [99; 4],
+            pitch_eg_levels : [50; 4],
+            algorithm : 0,
+            feedback : 0,
+            osc_key_sync : true,
+            lfo_speed : 35,
+            lfo_delay : 0,
+            lfo_pitch_depth : 0,
+            lfo_amp_depth : 0,
+            lfo_key_sync : true,
+            lfo_waveform : 0,
+            pitch_mod_sensitivity : 3,
+            transpose : 24,
+            name : *b"INIT VOICE",
+        }
+    }
+}
+
+/// engine envelope rate of DX7 rate 0, a sweep of about 40 s; the rate
+/// doubles every 6.24 steps as on the DX7
+const RATE_ZERO : f64 = 3.75e-5;
+const RATE_DOUBLING : f64 = 256.0 / 41.0;
+/// DX7 levels, output and envelope, step by 0.75 dB, an eighth of a doubling
+const LEVEL_DOUBLING : f64 = 8.0;
+/// DX7 feedback 7 modulates by half a cycle, each step below by half as much
+const FEEDBACK_MAX : u8 = 128;
+/// fixed frequencies are heard relative to middle C
+const MIDDLE_C : f64 = 261.6256;
+
+fn rate(rate : u8) -> FP {
+    FP::from((RATE_ZERO * (rate as f64 / RATE_DOUBLING).exp2()).min(1.0))
+}
+
+fn from_rate(rate : FP) -> u8 {
+    let rate = (rate.to_f32() as f64).max(RATE_ZERO);
+    ((rate / RATE_ZERO).log2() * RATE_DOUBLING).round().clamp(0.0, 99.0) as u8
+}
+
+/// amplitude 0..1 of a DX7 level
+fn amplitude(level : u8) -> f64 {
+    match level {
+        0 => 0.0,
+        _ => ((level as f64 - 99.0) / LEVEL_DOUBLING).exp2(),
+    }
+}
+
+fn from_amplitude(amplitude : f64) -> u8 {
+    match amplitude > 0.0 {
+        true => (99.0 + amplitude.log2() * LEVEL_DOUBLING).round().clamp(0.0, 99.0) as u8,
+        false => 0,
+    }
+}
+
+impl Dx7Patch {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).trim_end().to_string()
+    }
+
+    /// the voice as the engine plays it
+    pub fn to_voice(&self) -> Dx7Voice {
+        let mut voice = Dx7Voice::new();
+        let feedback = match self.feedback {
+            0 => 0,
+            level => FEEDBACK_MAX >> (7 - level),
+        };
+        voice.set_dx7_algorithm(self.algorithm as usize, feedback);
+
+        let transpose = (self.transpose as f64 - 24.0) / 12.0;
+        for (op, dx7) in voice.operators.iter_mut().zip(&self.operators) {
+            op.total_level = (255.0 * amplitude(dx7.output_level)).round() as u8;
+            let tune = match dx7.fixed {
+                true => ((dx7.coarse & 3) as f64 + dx7.fine as f64 / 100.0) * 10f64.log2() - MIDDLE_C.log2(),
+                false => {
+                    let coarse = if dx7.coarse == 0 { 0.5 } else { dx7.coarse as f64 };
+                    (coarse * (1.0 + dx7.fine as f64 / 100.0)).log2() + transpose
+                }
+            };
+            // a detune step is about a cent
+            op.phase_gen.tune = FP::from(tune + (dx7.detune as f64 - 7.0) / 1200.0);
+
+            // attack to full, decay to the third level, release
+            let env = &mut op.env_gen;
+            env.attack_rate = rate(dx7.eg_rates[0]);
+            env.decay_rate = rate(dx7.eg_rates[1]);
+            env.sustain_level = FP::from(amplitude(dx7.eg_levels[2]));
+            env.is_sustained = dx7.eg_levels[2] != 0;
+            env.release_rate = rate(dx7.eg_rates[3]);
+        }
+        voice
+    }
+
+    /// a DX7 patch sounding like `voice`, as far as the DX7 can; DX7
+    /// parameters the engine lacks are those of the INIT VOICE
+    pub fn from_voice(voice : &Dx7Voice) -> Dx7Patch {
+        let feedback = match voice.dx7_feedback_level() {
+            0 => 0,
+            level => (7.0 + (level as f64 / FEEDBACK_MAX as f64).log2()).round().clamp(1.0, 7.0) as u8,
+        };
+        let mut patch = Dx7Patch {
+            algorithm : voice.dx7_algorithm().unwrap_or(0) as u8,
+            feedback,
+            ..Dx7Patch::default()
+        };
+
+        for (dx7, op) in patch.operators.iter_mut().zip(&voice.operators) {
+            dx7.output_level = from_amplitude(op.total_level as f64 / 255.0);
+            let ratio = (op.phase_gen.tune.to_f32() as f64).exp2();
+            // the tune is a little off whole ratios in fixed point
+            let coarse = if ratio < 1.0 { 0.5 } else { (ratio + 1e-3).floor().min(31.0) };
+            dx7.coarse = coarse as u8;
+            dx7.fine = ((ratio / coarse - 1.0) * 100.0).round().clamp(0.0, 99.0) as u8;
+
+            let env = &op.env_gen;
+            dx7.eg_rates = [from_rate(env.attack_rate), from_rate(env.decay_rate),
+                from_rate(env.decay_rate), from_rate(env.release_rate)];
+            let sustain = if env.is_sustained { from_amplitude(env.sustain_level.to_f32() as f64) } else { 0 };
+            dx7.eg_levels = [99, 99, sustain, 0];
+        }
+        patch
+    }
+
+    fn unpacked(&self) -> [u8; VCED_SIZE] {
+        let mut data = [0; VCED_SIZE];
+        for (op, bytes) in self.operators.iter().zip(data.chunks_mut(OP_VCED_SIZE)) {
+            bytes[0..4].copy_from_slice(&op.eg_rates);
+            bytes[4..8].copy_from_slice(&op.eg_levels);
+            bytes[8..].copy_from_slice(&[op.break_point, op.left_depth, op.right_depth, op.left_curve,
+                op.right_curve, op.rate_scaling, op.amp_mod_sensitivity, op.velocity_sensitivity,
+                op.output_level, op.fixed as u8, op.coarse, op.fine, op.detune]);
+        }
+        let voice = &mut data[VOICE_OFFSET..];
+        voice[0..4].copy_from_slice(&self.pitch_eg_rates);
+        voice[4..8].copy_from_slice(&self.pitch_eg_levels);
+        voice[8..VOICE_PARAMS.len()].copy_from_slice(&[self.algorithm, self.feedback, self.osc_key_sync as u8,
+            self.lfo_speed, self.lfo_delay, self.lfo_pitch_depth, self.lfo_amp_depth, self.lfo_key_sync as u8,
+            self.lfo_waveform, self.pitch_mod_sensitivity, self.transpose]);
+        voice[VOICE_PARAMS.len()..].copy_from_slice(&self.name);
+        data
+    }
+
+    /// `voice` is the number of the voice in its message, for errors
+    fn from_unpacked(data : &[u8; VCED_SIZE], voice : usize) -> Result<Dx7Patch, SysexError> {
+        if let Some(offset) = (0..VCED_SIZE).find(|offset| data[*offset] > param(*offset).0) {
+            return Err(SysexError::Range(voice, offset, data[offset]));
+        }
+
+        let mut patch = Dx7Patch::default();
+        for (op, b) in patch.operators.iter_mut().zip(data.chunks(OP_VCED_SIZE)) {
+            *op = Dx7Operator {
+                eg_rates : [b[0], b[1], b[2], b[3]],
+                eg_levels : [b[4], b[5], b[6], b[7]],
+                break_point : b[8],
+                left_depth : b[9],
+                right_depth : b[10],
+                left_curve : b[11],
+                right_curve : b[12],
+                rate_scaling : b[13],
+                amp_mod_sensitivity : b[14],
+                velocity_sensitivity : b[15],
+                output_level : b[16],
+                fixed : b[17] != 0,
+                coarse : b[18],
+                fine : b[19],
+                detune : b[20],
+            };
+        }
+        let v = &data[VOICE_OFFSET..];
+        patch.pitch_eg_rates = [v[0], v[1], v[2], v[3]];
+        patch.pitch_eg_levels = [v[4], v[5], v[6], v[7]];
+        patch.algorithm = v[8];
+        patch.feedback = v[9];
+        patch.osc_key_sync = v[10] != 0;
+        patch.lfo_speed = v[11];
+        patch.lfo_delay = v[12];
+        patch.lfo_pitch_depth = v[13];
+        patch.lfo_amp_depth = v[14];
+        patch.lfo_key_sync = v[15] != 0;
+        patch.lfo_waveform = v[16];
+        patch.pitch_mod_sensitivity = v[17];
+        patch.transpose = v[18];
+        patch.name.copy_from_slice(&v[VOICE_PARAMS.len()..]);
+        Ok(patch)
+    }
+}
+
+/// a voice packed as in a bank
+fn pack(data : &[u8; VCED_SIZE]) -> [u8; VMEM_SIZE] {
+    let mut packed = [0; VMEM_SIZE];
+    for (op, bytes) in data.chunks(OP_VCED_SIZE).zip(packed.chunks_mut(OP_VMEM_SIZE)).take(DX7_OPERATORS) {
+        bytes[..11].copy_from_slice(&op[..11]);
+        bytes[11] = op[11] | op[12] << 2;
+        bytes[12] = op[13] | op[20] << 3;
+        bytes[13] = op[14] | op[15] << 2;
+        bytes[14] = op[16];
+        bytes[15] = op[17] | op[18] << 1;
+        bytes[16] = op[19];
+    }
+    let (v, p) = (&data[VOICE_OFFSET..], &mut packed[DX7_OPERATORS * OP_VMEM_SIZE..]);
+    p[..9].copy_from_slice(&v[..9]);
+    p[9] = v[9] | v[10] << 3;
+    p[10..14].copy_from_slice(&v[11..15]);
+    p[14] = v[15] | v[16] << 1 | v[17] << 4;
+    p[15] = v[18];
+    p[16..].copy_from_slice(&v[VOICE_PARAMS.len()..]);
+    packed
+}
+
+/// the parameters of a voice packed in a bank; bits beyond a field are
+/// ignored as on the DX7, values too high for a field are kept
+fn unpack(packed : &[u8]) -> [u8; VCED_SIZE] {
+    let mut data = [0; VCED_SIZE];
+    for (bytes, op) in packed.chunks(OP_VMEM_SIZE).zip(data.chunks_mut(OP_VCED_SIZE)).take(DX7_OPERATORS) {
+        op[..11].copy_from_slice(&bytes[..11]);
+        op[11] = bytes[11] & 3;
+        op[12] = bytes[11] >> 2 & 3;
+        op[13] = bytes[12] & 7;
+        op[20] = bytes[12] >> 3 & 15;
+        op[14] = bytes[13] & 3;
+        op[15] = bytes[13] >> 2 & 7;
+        op[16] = bytes[14];
+        op[17] = bytes[15] & 1;
+        op[18] = bytes[15] >> 1 & 31;
+        op[19] = bytes[16];
+    }
+    let (p, v) = (&packed[DX7_OPERATORS * OP_VMEM_SIZE..], &mut data[VOICE_OFFSET..]);
+    v[..8].copy_from_slice(&p[..8]);
+    v[8] = p[8] & 31;
+    v[9] = p[9] & 7;
+    v[10] = p[9] >> 3 & 1;
+    v[11..15].copy_from_slice(&p[10..14]);
+    v[15] = p[14] & 1;
+    v[16] = p[14] >> 1 & 7;
+    v[17] = p[14] >> 4 & 7;
+    v[18] = p[15];
+    v[VOICE_PARAMS.len()..].copy_from_slice(&p[16..]);
+    data
+}
+
+/// `data` with values too high for their parameter lowered to its highest
+fn clamp(data : &[u8; VCED_SIZE]) -> [u8; VCED_SIZE] {
+    core::array::from_fn(|offset| data[offset].min(param(offset).0))
+}
+
+fn checksum(data : &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg() & 0x7F
+}
+
+/// a message with `data` on MIDI channel 1
+fn message(format : u8, data : &[u8]) -> Vec<u8> {
+    let count = data.len() as u16;
+    let mut bytes = vec![SYSEX_START, YAMAHA, 0, format, (count >> 7) as u8, (count & 0x7F) as u8];
+    bytes.extend_from_slice(data);
+    bytes.extend([checksum(data), SYSEX_END]);
+    bytes
+}
+
+/// the voices of a single voice or bank message, on any MIDI channel.
+/// Values out of range are lowered to the highest of their parameter, as
+/// the DX7 does; `parse_strict` tells which voices have them.
+pub fn parse(bytes : &[u8]) -> Result<Vec<Dx7Patch>, SysexError> {
+    let voices = unpacked_voices(bytes)?;
+    voices.iter().enumerate()
+        .map(|(voice, data)| Dx7Patch::from_unpacked(&clamp(data), voice))
+        .collect()
+}
+
+/// the voices of a message as `parse` finds them, each an error if it
+/// has a value out of range
+pub fn parse_strict(bytes : &[u8]) -> Result<Vec<Result<Dx7Patch, SysexError>>, SysexError> {
+    let voices = unpacked_voices(bytes)?;
+    Ok(voices.iter().enumerate()
+        .map(|(voice, data)| Dx7Patch::from_unpacked(data, voice))
+        .collect())
+}
+
+/// the unpacked parameters of the voices of a message
+fn unpacked_voices(bytes : &[u8]) -> Result<Vec<[u8; VCED_SIZE]>, SysexError> {
+    const HEADER : usize = 6;
+    if bytes.len() < HEADER + 2 {
+        return Err(SysexError::Length(bytes.len()));
+    }
+    if bytes[0] != SYSEX_START || bytes[1] != YAMAHA || bytes[2] & 0xF0 != 0 {
+        return Err(SysexError::Header);
+    }
+    let size = match (bytes[3], bytes[4], bytes[5]) {
+        (FORMAT_VCED, 0x01, 0x1B) => VCED_SIZE,
+        (FORMAT_VMEM, 0x20, 0x00) => BANK_VOICES * VMEM_SIZE,
+        _ => return Err(SysexError::Header),
+    };
+    if bytes.len() != HEADER + size + 2 || bytes[bytes.len() - 1] != SYSEX_END {
+        return Err(SysexError::Length(bytes.len()));
+    }
+
+    let data = &bytes[HEADER..HEADER + size];
+    let stored = bytes[HEADER + size];
+    if stored != checksum(data) {
+        return Err(SysexError::Checksum(stored, checksum(data)));
+    }
+
+    match size {
+        VCED_SIZE => Ok(vec![data.try_into().unwrap()]),
+        _ => Ok(data.chunks(VMEM_SIZE).map(unpack).collect()),
+    }
+}
+
+/// a single voice message
+pub fn to_vced(patch : &Dx7Patch) -> Vec<u8> {
+    message(FORMAT_VCED, &patch.unpacked())
+}
+
+/// a bank message
+pub fn to_vmem(patches : &[Dx7Patch; BANK_VOICES]) -> Vec<u8> {
+    let data : Vec<u8> = patches.iter().flat_map(|patch| pack(&patch.unpacked())).collect();
+    message(FORMAT_VMEM, &data)
+}
+
+pub fn load<P : AsRef<Path>>(path : P) -> Result<Vec<Dx7Patch>, SysexError> {
+    parse(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::dx7::dx7_operator;
+    use crate::synth::random::Rng;
+    use crate::synth::voice_pool::VoicePool;
+
+    /// a patch with every parameter drawn in its range
+    fn random_patch(rng : &mut Rng) -> Dx7Patch {
+        let mut data = [0; VCED_SIZE];
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = rng.below(param(offset).0 as usize + 1) as u8;
+        }
+        Dx7Patch::from_unpacked(&data, 0).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = Rng::new(9);
+        let bank : [Dx7Patch; BANK_VOICES] = std::array::from_fn(|_| random_patch(&mut rng));
+        let bytes = to_vmem(&bank);
+        assert_eq!(bytes.len(), 4104);
+        assert_eq!(bytes[..6], [0xF0, 0x43, 0x00, 0x09, 0x20, 0x00]);
+        assert_eq!(parse(&bytes).unwrap(), bank);
+
+        let bytes = to_vced(&bank[3]);
+        assert_eq!(bytes.len(), 163);
+        assert_eq!(parse(&bytes).unwrap(), [bank[3]]);
+
+        let init = to_vced(&Dx7Patch::default());
+        assert_eq!(parse(&init).unwrap()[0].name(), "INIT VOICE");
+        // data and checksum add up to a multiple of 128
+        assert_eq!(init[6..162].iter().map(|byte| *byte as u32).sum::<u32>() % 128, 0);
+    }
+
+    #[test]
+    fn test_packing() {
+        let mut patch = Dx7Patch::default();
+        let op = &mut patch.operators[dx7_operator(2)];
+        (op.left_curve, op.right_curve) = (2, 1);
+        (op.rate_scaling, op.detune) = (5, 9);
+        (op.amp_mod_sensitivity, op.velocity_sensitivity) = (3, 6);
+        (op.fixed, op.coarse) = (true, 17);
+        (patch.feedback, patch.osc_key_sync) = (5, true);
+        (patch.lfo_key_sync, patch.lfo_waveform, patch.pitch_mod_sensitivity) = (true, 4, 6);
+
+        let packed = pack(&patch.unpacked());
+        let op = &packed[dx7_operator(2) * OP_VMEM_SIZE..];
+        assert_eq!(op[11..16], [2 | 1 << 2, 5 | 9 << 3, 3 | 6 << 2, 0, 1 | 17 << 1]);
+        assert_eq!(packed[111], 5 | 1 << 3);
+        assert_eq!(packed[116], 1 | 4 << 1 | 6 << 4);
+        assert_eq!(packed[118..], *b"INIT VOICE");
+        assert_eq!(unpack(&packed), patch.unpacked());
+    }
+
+    #[test]
+    fn test_errors() {
+        let bank = [Dx7Patch::default(); BANK_VOICES];
+        let good = to_vmem(&bank);
+
+        let mut bytes = good.clone();
+        bytes[100] ^= 1;
+        let expected = checksum(&bytes[6..4102]);
+        assert!(matches!(parse(&bytes), Err(SysexError::Checksum(stored, data))
+            if stored == good[4102] && data == expected));
+
+        assert!(matches!(parse(&good[..4000]), Err(SysexError::Length(4000))));
+        assert!(matches!(parse(&good[..3]), Err(SysexError::Length(3))));
+        let mut bytes = good.clone();
+        bytes[1] = 0x41;
+        assert!(matches!(parse(&bytes), Err(SysexError::Header)));
+        let mut bytes = good.clone();
+        bytes[3] = 0x02;
+        assert!(matches!(parse(&bytes), Err(SysexError::Header)));
+
+        // fine frequency of operator 1 in the third voice
+        let mut bytes = good;
+        let offset = 6 + 2 * VMEM_SIZE + dx7_operator(1) * OP_VMEM_SIZE + 16;
+        bytes[offset] = 120;
+        bytes[4102] = checksum(&bytes[6..4102]);
+        let voices = parse_strict(&bytes).unwrap();
+        assert_eq!(voices.iter().filter(|voice| voice.is_ok()).count(), BANK_VOICES - 1);
+        let error = voices[2].as_ref().unwrap_err();
+        assert!(matches!(error, SysexError::Range(2, 124, 120)), "{error:?}");
+        assert_eq!(error.to_string(), "voice 3: op1 frequency fine is 120, above 99");
+
+        // and clamped as on the DX7
+        let voices = parse(&bytes).unwrap();
+        assert_eq!(voices.len(), BANK_VOICES);
+        assert_eq!(voices[2].operators[dx7_operator(1)].fine, 99);
+        assert_eq!(voices[3], bank[3]);
+    }
+
+    #[test]
+    fn test_voice() {
+        let voice = Dx7Patch::default().to_voice();
+        assert_eq!(voice.dx7_algorithm(), Some(0));
+        let levels : Vec<u8> = voice.operators.iter().map(|op| op.total_level).collect();
+        assert_eq!(levels, [0, 0, 0, 0, 0, 255]);
+        assert!(voice.operators.iter().all(|op| op.phase_gen.tune == FP_ZERO));
+
+        let mut patch = Dx7Patch { algorithm : 4, feedback : 6, transpose : 36, ..Dx7Patch::default() };
+        let op = &mut patch.operators[dx7_operator(2)];
+        (op.coarse, op.fine, op.output_level) = (3, 50, 91);
+        op.eg_rates = [80, 40, 40, 60];
+        op.eg_levels = [99, 99, 70, 0];
+        let voice = patch.to_voice();
+        let op = &voice.operators[dx7_operator(2)];
+        assert_eq!(op.total_level, 128);
+        assert!((op.phase_gen.tune.to_f32() - (4.5f32.log2() + 1.0)).abs() < 1e-4);
+        assert_eq!(voice.operators[dx7_operator(6)].feedback_level, 64);
+
+        let back = Dx7Patch::from_voice(&voice);
+        assert_eq!((back.algorithm, back.feedback), (4, 6));
+        let op = &back.operators[dx7_operator(2)];
+        // the transposition is in the ratio now
+        assert_eq!((op.coarse, op.fine, op.output_level), (9, 0, 91));
+        assert_eq!((op.eg_rates, op.eg_levels), ([80, 40, 40, 60], [99, 99, 70, 0]));
+
+        let mut pool = VoicePool::new(voice);
+        pool.note_on(57, 100);
+        assert!((0..4800).map(|_| pool.get_sample().abs()).fold(0.0, f32::max) > 0.1);
+    }
+}
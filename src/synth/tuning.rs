@@ -0,0 +1,452 @@
+//! tuning
+//!
+//! maps keys to log2 frequencies (the `flog2` that `Voice::note_on` takes).
+//! Scales and keyboard mappings can be loaded from Scala .scl and .kbm
+//! files, or built from the equal temperament and just intonation presets.
+
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::fp::*;
+
+pub const NUM_KEYS : usize = 128;
+
+/// log2(440)
+const LOG2_440 : f64 = 8.78135971352466;
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum TuningError {
+    Io(std::io::Error),
+    MissingLine(&'static str),
+    BadNumber(String),
+    BadPitch(String),
+    EmptyScale,
+    BadKey(i64),
+    BadMapSize(usize),
+    BadDegree(usize),
+    UnmappedReference(u8),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for TuningError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TuningError::Io(e)                => write!(f, "i/o error: {e}"),
+            TuningError::MissingLine(what)    => write!(f, "missing line: {what}"),
+            TuningError::BadNumber(s)         => write!(f, "not a number: '{s}'"),
+            TuningError::BadPitch(s)          => write!(f, "not a pitch: '{s}'"),
+            TuningError::EmptyScale           => write!(f, "scale has no degrees"),
+            TuningError::BadKey(k)            => write!(f, "key {k} out of range 0..127"),
+            TuningError::BadMapSize(n)        => write!(f, "map size {n} above {NUM_KEYS}"),
+            TuningError::BadDegree(d)         => write!(f, "scale degree {d} out of range"),
+            TuningError::UnmappedReference(k) => write!(f, "reference key {k} is not mapped"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TuningError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for TuningError {
+    fn from(e : std::io::Error) -> Self {
+        TuningError::Io(e)
+    }
+}
+
+/// A scale as in a Scala .scl file: the degrees above 1/1, as log2 ratios.
+/// The last degree is the period (formal octave) of the scale.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description : String,
+    pub degrees : Vec<f64>,
+}
+
+#[cfg(feature = "std")]
+impl Scale {
+    /// `divisions` equal steps per octave
+    pub fn edo(divisions : usize) -> Scale {
+        Scale {
+            description : format!("{divisions} equal divisions of the octave"),
+            degrees : (1..=divisions).map(|i| i as f64 / divisions as f64).collect(),
+        }
+    }
+
+    /// 12-tone 5-limit just intonation
+    pub fn just_5_limit() -> Scale {
+        Self::from_ratios("12-tone 5-limit just intonation", &[
+            (16, 15), (9, 8), (6, 5), (5, 4), (4, 3), (45, 32),
+            (3, 2), (8, 5), (5, 3), (9, 5), (15, 8), (2, 1)
+        ])
+    }
+
+    /// 12-tone 7-limit just intonation
+    pub fn just_7_limit() -> Scale {
+        Self::from_ratios("12-tone 7-limit just intonation", &[
+            (15, 14), (8, 7), (6, 5), (5, 4), (4, 3), (7, 5),
+            (3, 2), (8, 5), (5, 3), (7, 4), (15, 8), (2, 1)
+        ])
+    }
+
+    /// 12-tone Pythagorean tuning (chain of pure fifths)
+    pub fn pythagorean() -> Scale {
+        Self::from_ratios("12-tone Pythagorean", &[
+            (256, 243), (9, 8), (32, 27), (81, 64), (4, 3), (729, 512),
+            (3, 2), (128, 81), (27, 16), (16, 9), (243, 128), (2, 1)
+        ])
+    }
+
+    fn from_ratios(description : &str, ratios : &[(u32, u32)]) -> Scale {
+        Scale {
+            description : String::from(description),
+            degrees : ratios.iter().map(|(n, d)| (*n as f64 / *d as f64).log2()).collect(),
+        }
+    }
+
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Scale, TuningError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// parse the contents of a .scl file
+    pub fn parse(text : &str) -> Result<Scale, TuningError> {
+        // the description may be empty, so only comments are skipped here
+        let mut lines = text.lines().filter(|l| !l.starts_with('!'));
+
+        let description = lines.next()
+            .ok_or(TuningError::MissingLine("description"))?
+            .trim().to_string();
+        let count = lines.next()
+            .ok_or(TuningError::MissingLine("number of notes"))
+            .and_then(|l| parse_int(first_token(l)))?;
+
+        let mut degrees = Vec::new();
+        for _ in 0..count {
+            let line = lines.next().ok_or(TuningError::MissingLine("pitch"))?;
+            degrees.push(parse_pitch(first_token(line))?);
+        }
+        if degrees.is_empty() {
+            return Err(TuningError::EmptyScale);
+        }
+
+        Ok(Scale { description, degrees })
+    }
+
+    pub fn period(&self) -> f64 {
+        self.degrees[self.degrees.len() - 1]
+    }
+
+    /// log2 ratio of `degree` above 1/1; degrees past the end continue
+    /// into the next periods
+    pub fn pitch(&self, degree : i64) -> f64 {
+        let len = self.degrees.len() as i64;
+        let period = degree.div_euclid(len);
+        let step = degree.rem_euclid(len);
+        let base = if step == 0 { 0.0 } else { self.degrees[step as usize - 1] };
+        period as f64 * self.period() + base
+    }
+}
+
+/// A keyboard mapping as in a Scala .kbm file
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMap {
+    pub first_key : u8,
+    pub last_key : u8,
+    /// key on which scale degree 0 (1/1) is mapped
+    pub middle_key : u8,
+    pub reference_key : u8,
+    pub reference_freq : f64,
+    /// scale degree acting as formal octave; 0 means the scale's period
+    pub octave_degree : usize,
+    /// scale degree per key in one repetition of the map, None if unmapped.
+    /// An empty map is a linear mapping, one degree per key.
+    pub mapping : Vec<Option<usize>>,
+}
+
+#[cfg(feature = "std")]
+impl KeyboardMap {
+    /// one degree per key, degree 0 on `middle_key`
+    pub fn linear(middle_key : u8, reference_key : u8, reference_freq : f64) -> KeyboardMap {
+        KeyboardMap {
+            first_key : 0,
+            last_key : (NUM_KEYS - 1) as u8,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree : 0,
+            mapping : Vec::new(),
+        }
+    }
+
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<KeyboardMap, TuningError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// parse the contents of a .kbm file
+    pub fn parse(text : &str) -> Result<KeyboardMap, TuningError> {
+        let mut lines = text.lines()
+            .filter(|l| !l.starts_with('!') && !l.trim().is_empty())
+            .map(first_token);
+
+        let mut next = |what| lines.next().ok_or(TuningError::MissingLine(what));
+
+        let size = parse_int(next("map size")?)?;
+        let first_key = parse_key(next("first key")?)?;
+        let last_key = parse_key(next("last key")?)?;
+        let middle_key = parse_key(next("middle key")?)?;
+        let reference_key = parse_key(next("reference key")?)?;
+        let freq = next("reference frequency")?;
+        let reference_freq = freq.parse::<f64>()
+            .map_err(|_| TuningError::BadNumber(freq.to_string()))?;
+        let octave_degree = parse_int(next("octave degree")?)?;
+
+        // a map repeats every `size` keys, so a larger one maps no more keys
+        if size > NUM_KEYS {
+            return Err(TuningError::BadMapSize(size));
+        }
+
+        // a map may list fewer entries than its size, the rest is unmapped
+        let mut mapping = vec![None; size];
+        for entry in mapping.iter_mut() {
+            match next("mapping") {
+                Ok("x") | Ok("X") => (),
+                Ok(degree) => *entry = Some(parse_int(degree)?),
+                Err(_) => break,
+            }
+        }
+
+        Ok(KeyboardMap {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree,
+            mapping,
+        })
+    }
+
+    /// scale degree (relative to 1/1 on the middle key) for `key`
+    fn degree(&self, key : u8, scale : &Scale) -> Result<Option<i64>, TuningError> {
+        let offset = key as i64 - self.middle_key as i64;
+        if self.mapping.is_empty() {
+            return Ok(Some(offset));
+        }
+
+        let size = self.mapping.len() as i64;
+        let repeat = offset.div_euclid(size);
+        let octave = match self.octave_degree {
+            0 => scale.degrees.len(),
+            d => d,
+        } as i64;
+
+        match self.mapping[offset.rem_euclid(size) as usize] {
+            None => Ok(None),
+            Some(d) if d > scale.degrees.len() => Err(TuningError::BadDegree(d)),
+            Some(d) => Ok(Some(repeat * octave + d as i64)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for KeyboardMap {
+    /// degree 0 on middle C, A4 = 440Hz
+    fn default() -> Self {
+        KeyboardMap::linear(60, 69, 440.0)
+    }
+}
+
+/// Key to log2 frequency table
+#[derive(Debug, Copy, Clone)]
+pub struct Tuning {
+    table : [Option<FP>; NUM_KEYS],
+}
+
+impl Tuning {
+    /// from a table of log2 frequencies, None for unmapped keys
+    pub fn from_table(table : [Option<FP>; NUM_KEYS]) -> Tuning {
+        Tuning { table }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new(scale : &Scale, map : &KeyboardMap) -> Result<Tuning, TuningError> {
+        if scale.degrees.is_empty() {
+            return Err(TuningError::EmptyScale);
+        }
+
+        let reference = map.degree(map.reference_key, scale)?
+            .ok_or(TuningError::UnmappedReference(map.reference_key))?;
+        let base = map.reference_freq.log2() - scale.pitch(reference);
+
+        let mut table = [None; NUM_KEYS];
+        for key in map.first_key..=map.last_key.min((NUM_KEYS - 1) as u8) {
+            if let Some(degree) = map.degree(key, scale)? {
+                table[key as usize] = Some(FP::from(base + scale.pitch(degree)));
+            }
+        }
+
+        Ok(Tuning { table })
+    }
+
+    /// `divisions` equal steps per octave, degree 0 on middle C, A4 = 440Hz;
+    /// no divisions is an empty scale
+    #[cfg(feature = "std")]
+    pub fn equal(divisions : usize) -> Result<Tuning, TuningError> {
+        Self::new(&Scale::edo(divisions), &KeyboardMap::default())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P : AsRef<Path>>(scl : P, kbm : Option<P>) -> Result<Tuning, TuningError> {
+        let map = match kbm {
+            Some(path) => KeyboardMap::load(path)?,
+            None => KeyboardMap::default(),
+        };
+        Self::new(&Scale::load(scl)?, &map)
+    }
+
+    /// log2 frequency of `key`, None if the key is not mapped
+    pub fn flog2(&self, key : u8) -> Option<FP> {
+        self.table.get(key as usize).copied().flatten()
+    }
+}
+
+impl Default for Tuning {
+    /// 12-tone equal temperament, A4 = 440Hz
+    fn default() -> Self {
+        // the table of `Tuning::equal(12)`, worked out step by step as
+        // `Scale::pitch` does, since core has no f64 log2
+        let base = LOG2_440 - 0.75;
+        let mut table = [None; NUM_KEYS];
+        for (key, entry) in table.iter_mut().enumerate() {
+            let degree = key as i64 - 60;
+            let step = degree.rem_euclid(12);
+            let pitch = if step == 0 { 0.0 } else { step as f64 / 12.0 };
+            *entry = Some(FP::from(base + (degree.div_euclid(12) as f64 + pitch)));
+        }
+        Tuning { table }
+    }
+}
+
+#[cfg(feature = "std")]
+fn first_token(line : &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+#[cfg(feature = "std")]
+fn parse_int(s : &str) -> Result<usize, TuningError> {
+    s.parse::<usize>().map_err(|_| TuningError::BadNumber(s.to_string()))
+}
+
+#[cfg(feature = "std")]
+fn parse_key(s : &str) -> Result<u8, TuningError> {
+    let key = s.parse::<i64>().map_err(|_| TuningError::BadNumber(s.to_string()))?;
+    if !(0..NUM_KEYS as i64).contains(&key) {
+        return Err(TuningError::BadKey(key));
+    }
+    Ok(key as u8)
+}
+
+/// a pitch is either cents (contains a '.') or a ratio 'n/d' or 'n'
+#[cfg(feature = "std")]
+fn parse_pitch(s : &str) -> Result<f64, TuningError> {
+    let bad = || TuningError::BadPitch(s.to_string());
+
+    if s.contains('.') {
+        let cents = s.parse::<f64>().map_err(|_| bad())?;
+        return Ok(cents / 1200.0);
+    }
+
+    let (num, den) = match s.split_once('/') {
+        Some((n, d)) => (n, d),
+        None => (s, "1"),
+    };
+    let num = num.parse::<u64>().map_err(|_| bad())?;
+    let den = den.parse::<u64>().map_err(|_| bad())?;
+    if num == 0 || den == 0 {
+        return Err(bad());
+    }
+    Ok((num as f64 / den as f64).log2())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn freq(tuning : &Tuning, key : u8) -> f32 {
+        tuning.flog2(key).unwrap().to_f32().exp2()
+    }
+
+    #[test]
+    fn test_equal() {
+        let tuning = Tuning::default();
+        assert_eq!(tuning.flog2(57), Some(FP::raw(0x7_c807))); // log2(220)
+        assert!((freq(&tuning, 69) - 440.0).abs() < 0.01);
+        assert!((freq(&tuning, 60) - 261.63).abs() < 0.01);
+        assert!((freq(&tuning, 81) - 880.0).abs() < 0.01);
+
+        assert_eq!(LOG2_440, 440.0f64.log2());
+        assert_eq!(tuning.table, Tuning::equal(12).unwrap().table);
+        assert!(matches!(Tuning::equal(0), Err(TuningError::EmptyScale)));
+
+        let tuning = Tuning::equal(19).unwrap();
+        assert!((freq(&tuning, 69) - 440.0).abs() < 0.01);
+        assert!((freq(&tuning, 69 + 19) - 880.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_scl() {
+        let scl = "! meantone.scl\n\
+                   !\n\
+                   Test scale\n \
+                   3\n\
+                   !\n \
+                   386.31371 major third\n \
+                   3/2\n \
+                   2\n";
+        let scale = Scale::parse(scl).unwrap();
+        assert_eq!(scale.description, "Test scale");
+        assert_eq!(scale.degrees.len(), 3);
+        assert!((scale.degrees[0] - 1.25f64.log2()).abs() < 1e-6);
+        assert_eq!(scale.degrees[1], 1.5f64.log2());
+        assert_eq!(scale.period(), 1.0);
+        assert_eq!(scale.pitch(-3), -1.0);
+        assert_eq!(scale.pitch(5), 1.0 + 1.5f64.log2());
+
+        assert!(matches!(Scale::parse("x\n1\n0/1\n"), Err(TuningError::BadPitch(_))));
+        assert!(matches!(Scale::parse("x\n2\n3/2\n"), Err(TuningError::MissingLine(_))));
+        assert!(matches!(Scale::parse("x\n0\n"), Err(TuningError::EmptyScale)));
+    }
+
+    #[test]
+    fn test_kbm() {
+        // white keys only, C major on a just scale
+        let kbm = "! white.kbm\n\
+                   12\n0\n127\n60\n69\n440.0\n7\n\
+                   ! mapping\n\
+                   0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n";
+        let map = KeyboardMap::parse(kbm).unwrap();
+        assert_eq!(map.mapping.len(), 12);
+        assert_eq!(map.mapping[1], None);
+        assert_eq!(map.mapping[11], Some(6));
+
+        let scale = Scale::from_ratios("just major", &[
+            (9, 8), (5, 4), (4, 3), (3, 2), (5, 3), (15, 8), (2, 1)
+        ]);
+        let tuning = Tuning::new(&scale, &map).unwrap();
+        assert!((freq(&tuning, 69) - 440.0).abs() < 0.01);
+        assert!((freq(&tuning, 60) - 264.0).abs() < 0.01);
+        assert!((freq(&tuning, 67) - 396.0).abs() < 0.01);
+        assert!((freq(&tuning, 72) - 528.0).abs() < 0.02);
+        assert_eq!(tuning.flog2(61), None);
+        assert_eq!(tuning.flog2(73), None);
+
+        let huge = "5000\n0\n127\n60\n69\n440.0\n0\n";
+        assert!(matches!(KeyboardMap::parse(huge), Err(TuningError::BadMapSize(5000))));
+    }
+}
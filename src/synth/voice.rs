@@ -1,31 +1,58 @@
+#[cfg(feature = "rodio")]
 use std::time::Duration;
-use std::iter::zip;
+#[cfg(feature = "rodio")]
 use rodio::source::Source;
 
 use crate::fp::*;
 
 use super::operator::*;
+use super::pitch::*;
+use super::env_generator::EnvState;
+use super::ramp::Smoother;
 
+/// a voice of `N` operators, computed in order
 #[derive(Debug, Copy, Clone)]
-pub struct Voice {
-    pub operators : [ Operator; 4 ],
-    pub algorithm : usize,
-    output : FP,
-    adder : FP,
+pub struct FmVoice<const N : usize> {
+    pub operators : [ Operator; N ],
+    pub routing : Routing<N>,
+    pub pitch : Pitch,
+    /// output of each operator in the last sample, for the feedback paths
+    outputs : [FP; N],
+    /// the routing as played, ramping to changes of `routing`
+    played : RoutingSmoother<N>,
+}
+
+/// the four operator voice that patches, plugins and bindings use, and the
+/// only one the parameter registry addresses
+pub type Voice = FmVoice<4>;
+
+impl<const N : usize> Default for FmVoice<N> {
+    fn default() -> FmVoice<N> {
+        FmVoice::new()
+    }
 }
 
 impl Voice {
-    pub fn new() -> Voice {
-        Voice {
-            operators : [
-                Operator::new(),
-                Operator::new(),
-                Operator::new(),
-                Operator::new()
-            ],
-            algorithm : 0,
-            output : FP_ZERO,
-            adder : FP_ZERO,
+    /// route the operators as preset `algorithm`
+    pub fn set_algorithm(&mut self, algorithm : usize) {
+        self.routing = ALGORITHMS[algorithm];
+    }
+
+    /// the preset the routing is, if any
+    pub fn algorithm(&self) -> Option<usize> {
+        ALGORITHMS.iter().position(|preset| *preset == self.routing)
+    }
+}
+
+impl<const N : usize> FmVoice<N> {
+    /// operators in a stack, the last one heard
+    pub fn new() -> FmVoice<N> {
+        FmVoice {
+            operators : [Operator::new(); N],
+            routing : Routing::stack(),
+            pitch : Pitch::new(),
+            outputs : [FP_ZERO; N],
+            played : RoutingSmoother::new(&Routing::stack()),
         }
     }
 
@@ -34,46 +61,88 @@ impl Voice {
     }
 
     pub fn get_sample(&mut self) -> f32 {
-        self.output = FP_ZERO;
-        self.adder = FP_ZERO;
-        let algo = &ALGORITHMS[self.algorithm];
-        for (op, i) in zip(&mut self.operators, 0..4) {
-            op.mod_input = 
-                match algo[i].mod_source {
-                    Register::Null => FP_ZERO,
-                    Register::Output => self.output,
-                    Register::Adder => self.adder
-                };
-            let sample = op.get_sample();
-            match algo[i].out_sink {
-                Register::Output => self.output = sample,
-                Register::Adder  => self.adder = self.adder + sample,
-                Register::Null => ()
-            };
+        let flog2 = self.pitch.next();
+        for op in &mut self.operators {
+            op.phase_gen.flog2 = flog2;
         }
 
-        // Output sink of last op is final output
-        return 
-            match algo[3].out_sink {
-                Register::Null => 0.0,
-                Register::Output => self.output.to_f32(),
-                Register::Adder  => self.adder.to_f32(),
-            };
+        let routing = &self.routing;
+        let played = &mut self.played;
+        let mut outputs = [FP_ZERO; N];
+        let mut sample = FP_ZERO;
+        for (i, op) in self.operators.iter_mut().enumerate() {
+            // connections into an operator ramp over its smoothing time
+            let samples = op.smoothing;
+            let mut mod_input = FP_ZERO;
+            for (src, output) in outputs[..i].iter().enumerate() {
+                let depth = played.modulation[src][i].next(routing.modulation[src][i], samples);
+                if depth != FP_ZERO {
+                    mod_input += *output * depth;
+                }
+            }
+            // later operators have not run yet, so they feed back their
+            // output of the last sample
+            for (src, output) in self.outputs.iter().enumerate().skip(i + 1) {
+                let depth = played.feedback[src][i].next(routing.feedback[src][i], samples);
+                if depth != FP_ZERO {
+                    mod_input += *output * depth;
+                }
+            }
+            op.mod_input = mod_input;
+            outputs[i] = op.get_sample();
+
+            let level = played.output[i].next(routing.output[i], samples);
+            if level != FP_ZERO {
+                sample += outputs[i] * level;
+            }
+        }
+        self.outputs = outputs;
+
+        return sample.to_f32();
     }
 
+    /// change pitch, with portamento if the glide time is set
     pub fn set_freq(&mut self, flog2 : FP) {
-        for op in &mut self.operators {
-            op.phase_gen.flog2 = flog2;
-        }
+        self.pitch.glide_to(flog2);
+    }
+
+    /// `value` is a centered MIDI pitch bend, -8192..8191
+    pub fn set_bend(&mut self, value : i32) {
+        self.pitch.set_bend(value);
     }
 
     pub fn note_on(&mut self, flog2 : FP) {
+        // a silent voice need not ramp to parameters changed meanwhile
+        if !self.is_active() {
+            for op in &mut self.operators {
+                op.settle();
+            }
+            self.played = RoutingSmoother::new(&self.routing);
+        }
+        self.pitch.glide_to(flog2);
         for op in &mut self.operators {
-            op.phase_gen.flog2 = flog2;
             op.env_gen.open();
         }
     }
 
+    /// samples over which operator level, feedback and tune changes are
+    /// smoothed, and changes of the routing into and out of each operator
+    pub fn set_smoothing(&mut self, samples : u32) {
+        for op in &mut self.operators {
+            op.smoothing = samples;
+        }
+    }
+
+    /// true while any operator envelope is not idle
+    pub fn is_active(&self) -> bool {
+        self.operators.iter().any(|op| op.env_gen.state() != EnvState::Idle)
+    }
+
+    /// which operators are heard directly rather than modulating another
+    pub fn carriers(&self) -> [bool; N] {
+        self.routing.carriers()
+    }
+
     pub fn note_off(&mut self) {
         for op in &mut self.operators {
             op.env_gen.close();
@@ -81,7 +150,7 @@ impl Voice {
     }
 }
 
-impl Iterator for Voice {
+impl<const N : usize> Iterator for FmVoice<N> {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
@@ -89,7 +158,8 @@ impl Iterator for Voice {
     }
 }
 
-impl Source for Voice {
+#[cfg(feature = "rodio")]
+impl<const N : usize> Source for FmVoice<N> {
     fn channels(&self) -> u16 {
         return 1;
     }
@@ -107,92 +177,140 @@ impl Source for Voice {
      }
 }
 
-enum Register {
-    Null,
-    Output,
-    Adder
+/// how the operators of a voice connect: each operator is modulated by the
+/// outputs of the operators before it, each at its own depth, and the voice
+/// sounds the outputs of its carriers, each at its own level. These outputs
+/// are of the current sample, so modulation only runs from lower to higher
+/// operators.
+///
+/// Feedback paths run the other way: an operator is modulated by the output
+/// a later operator had in the previous sample. A loop through several
+/// operators, as 1>2>3 with 3 feeding back into 1, is thus one sample
+/// late, like the feedback of an operator into itself.
+///
+/// A playing voice ramps to a changed depth or level over the smoothing
+/// time of the operator it leads into, or of the carrier for its level.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Routing<const N : usize> {
+    /// `modulation[src][dst]`: depth at which operator `src` modulates
+    /// operator `dst`; only used for `src < dst`
+    pub modulation : [[FP; N]; N],
+    /// output level of each operator; operators with a level are carriers
+    pub output : [FP; N],
+    /// `feedback[src][dst]`: depth at which the previous output of operator
+    /// `src` modulates operator `dst`; only used for `src > dst`, as an
+    /// operator feeds back into itself by its `feedback_level`
+    pub feedback : [[FP; N]; N],
+}
+
+impl<const N : usize> Routing<N> {
+    /// no modulation, no carriers and no feedback
+    pub const fn new() -> Routing<N> {
+        Routing { modulation : [[FP_ZERO; N]; N], output : [FP_ZERO; N], feedback : [[FP_ZERO; N]; N] }
+    }
+
+    /// each operator modulating the next, the last one heard
+    pub const fn stack() -> Routing<N> {
+        let mut routing = Routing::new();
+        let mut i = 1;
+        while i < N {
+            routing.modulation[i - 1][i] = FP_ONE;
+            i += 1;
+        }
+        routing.output[N - 1] = FP_ONE;
+        routing
+    }
+
+    /// full depth modulation for each `(src, dst)` and full level for each
+    /// carrier, operators numbered from 1 as in the diagrams
+    const fn preset(modulation : &[(usize, usize)], carriers : &[usize]) -> Routing<N> {
+        let mut routing = Routing::new();
+        let mut i = 0;
+        while i < modulation.len() {
+            let (src, dst) = modulation[i];
+            routing.modulation[src - 1][dst - 1] = FP_ONE;
+            i += 1;
+        }
+        let mut i = 0;
+        while i < carriers.len() {
+            routing.output[carriers[i] - 1] = FP_ONE;
+            i += 1;
+        }
+        routing
+    }
+
+    pub fn carriers(&self) -> [bool; N] {
+        self.output.map(|level| level != FP_ZERO)
+    }
+}
+
+impl<const N : usize> Default for Routing<N> {
+    fn default() -> Routing<N> {
+        Routing::new()
+    }
+}
+
+/// a smoother for every depth and level of a routing
+#[derive(Debug, Copy, Clone)]
+struct RoutingSmoother<const N : usize> {
+    modulation : [[Smoother; N]; N],
+    output : [Smoother; N],
+    feedback : [[Smoother; N]; N],
 }
 
-struct Route {
-    mod_source : Register,
-    out_sink : Register
+impl<const N : usize> RoutingSmoother<N> {
+    /// settled on `routing`
+    fn new(routing : &Routing<N>) -> RoutingSmoother<N> {
+        let matrix = |depths : &[[FP; N]; N]| core::array::from_fn(|src|
+            core::array::from_fn(|dst| Smoother::new(depths[src][dst])));
+        RoutingSmoother {
+            modulation : matrix(&routing.modulation),
+            output : routing.output.map(Smoother::new),
+            feedback : matrix(&routing.feedback),
+        }
+    }
 }
 
-type Algorithm = [Route ; 4];
+pub const ALGORITHM_COUNT : usize = 8;
 
-const ALGORITHMS : [Algorithm; 8] = 
-[
+/// the classic algorithms, as presets of the routing
+pub const ALGORITHMS : [Routing<4>; ALGORITHM_COUNT] = [
     // [1]-[2]-[3]-[4]->
-    [ 
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Output }
-    ], 
+    Routing::preset(&[(1, 2), (2, 3), (3, 4)], &[4]),
 
     // [1]-.
     //     |
     // [2]-+-[3]-[4]->
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Adder,  out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Output }
-    ],
+    Routing::preset(&[(1, 3), (2, 3), (3, 4)], &[4]),
 
     //     [1]-.
     //         |
     // [2]-[3]-+-[4]->
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Adder,  out_sink : Register::Output }
-    ],
+    Routing::preset(&[(2, 3), (1, 4), (3, 4)], &[4]),
 
     //         [1]-.
     //             |
     // [2]-[3]-[4]-+->
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }
-    ],
+    Routing::preset(&[(2, 3), (3, 4)], &[1, 4]),
 
     // [1]-[2]-.
     //         |
     // [3]-[4]-+->
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }
-    ],
+    Routing::preset(&[(1, 2), (3, 4)], &[2, 4]),
 
     //     .-[2]-.
     //     |     |
     // [1]-+-[3]-+->
     //     |     |
     //     `-[4]-´
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }
-    ],
+    Routing::preset(&[(1, 2), (1, 3), (1, 4)], &[2, 3, 4]),
 
     // [1]-----.
     //         |
     // [2]-[3]-+->
     //         |
     // [4]-----´
-[
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Output }, 
-        Route { mod_source : Register::Output, out_sink : Register::Adder  }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Adder  }
-    ],
+    Routing::preset(&[(2, 3)], &[1, 3, 4]),
 
     // [1]-.
     //     |
@@ -201,10 +319,210 @@ const ALGORITHMS : [Algorithm; 8] =
     // [3]-+->
     //     |
     // [4]-´
-    [
-        Route { mod_source : Register::Null,   out_sink : Register::Adder }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Adder }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Adder }, 
-        Route { mod_source : Register::Null,   out_sink : Register::Adder }
-    ],
-];
\ No newline at end of file
+    Routing::preset(&[], &[1, 2, 3, 4]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the register machine the algorithms ran on before the routing: each
+    /// operator reads its modulation from a register and writes its output
+    /// to one, and the last operator's register is heard
+    #[derive(Copy, Clone, PartialEq)]
+    enum Register {
+        Null,
+        Output,
+        Adder,
+    }
+    use Register::*;
+
+    const REGISTERS : [[(Register, Register); 4]; ALGORITHM_COUNT] = [
+        [(Null, Output), (Output, Output), (Output, Output), (Output, Output)],
+        [(Null, Adder), (Null, Adder), (Adder, Output), (Output, Output)],
+        [(Null, Adder), (Null, Output), (Output, Adder), (Adder, Output)],
+        [(Null, Adder), (Null, Output), (Output, Output), (Output, Adder)],
+        [(Null, Output), (Output, Adder), (Null, Output), (Output, Adder)],
+        [(Null, Output), (Output, Adder), (Output, Adder), (Output, Adder)],
+        [(Null, Adder), (Null, Output), (Output, Adder), (Null, Adder)],
+        [(Null, Adder), (Null, Adder), (Null, Adder), (Null, Adder)],
+    ];
+
+    fn register_sample(voice : &mut Voice, algorithm : usize) -> f32 {
+        let flog2 = voice.pitch.next();
+        let (mut output, mut adder) = (FP_ZERO, FP_ZERO);
+        for (op, (source, sink)) in voice.operators.iter_mut().zip(REGISTERS[algorithm]) {
+            op.phase_gen.flog2 = flog2;
+            op.mod_input = match source {
+                Null => FP_ZERO,
+                Output => output,
+                Adder => adder,
+            };
+            let sample = op.get_sample();
+            match sink {
+                Output => output = sample,
+                Adder => adder += sample,
+                Null => (),
+            }
+        }
+        return match REGISTERS[algorithm][3].1 {
+            Null => 0.0,
+            Output => output.to_f32(),
+            Adder => adder.to_f32(),
+        };
+    }
+
+    #[test]
+    fn test_carriers() {
+        let mut voice = Voice::new();
+        let expected = [
+            [false, false, false, true],
+            [false, false, false, true],
+            [false, false, false, true],
+            [true, false, false, true],
+            [false, true, false, true],
+            [false, true, true, true],
+            [true, false, true, true],
+            [true, true, true, true],
+        ];
+        for (algorithm, carriers) in expected.iter().enumerate() {
+            voice.set_algorithm(algorithm);
+            assert_eq!(voice.carriers(), *carriers, "algorithm {}", algorithm + 1);
+            assert_eq!(voice.algorithm(), Some(algorithm));
+        }
+        assert_eq!(Routing::stack(), ALGORITHMS[0]);
+    }
+
+    #[test]
+    fn test_presets() {
+        for algorithm in 0..ALGORITHM_COUNT {
+            let mut voice = Voice::new();
+            for (i, op) in voice.operators.iter_mut().enumerate() {
+                op.total_level = 255 - 40 * i as u8;
+                op.feedback_level = 60;
+                op.phase_gen.tune = FP::from(i as i32 - 1);
+                op.env_gen.attack_rate = FP_ONE;
+            }
+            voice.set_algorithm(algorithm);
+            voice.note_on(FP::from(8.5));
+            let mut registers = voice;
+            for _ in 0..2000 {
+                assert_eq!(voice.get_sample(), register_sample(&mut registers, algorithm),
+                    "algorithm {}", algorithm + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut voice = Voice::new();
+        for op in &mut voice.operators {
+            op.env_gen.attack_rate = FP_ONE;
+        }
+
+        // op1 modulating op4 at half depth, heard at half level
+        voice.routing = Routing::new();
+        voice.routing.modulation[0][3] = FP::from(0.5);
+        voice.routing.output[3] = FP::from(0.5);
+        assert_eq!(voice.algorithm(), None);
+        assert_eq!(voice.carriers(), [false, false, false, true]);
+
+        let mut carrier = voice;
+        carrier.routing.modulation[0][3] = FP_ZERO;
+        carrier.routing.output[3] = FP_ONE;
+        voice.note_on(FP::from(8.5));
+        carrier.note_on(FP::from(8.5));
+        let mut modulated = false;
+        for _ in 0..2000 {
+            let sample = voice.get_sample();
+            let plain = carrier.get_sample();
+            assert!(sample.abs() <= 0.5);
+            modulated |= (sample - plain * 0.5).abs() > 0.01;
+        }
+        assert!(modulated);
+
+        // depth at or after the destination has no effect
+        let mut reversed = Voice::new();
+        reversed.routing = Routing::new();
+        reversed.routing.modulation[3][0] = FP_ONE;
+        reversed.routing.output[0] = FP_ONE;
+        let mut plain = reversed;
+        plain.routing.modulation[3][0] = FP_ZERO;
+        for voice in [&mut reversed, &mut plain] {
+            voice.operators[0].env_gen.attack_rate = FP_ONE;
+            voice.operators[3].env_gen.attack_rate = FP_ONE;
+            voice.note_on(FP::from(8.5));
+        }
+        for _ in 0..500 {
+            assert_eq!(reversed.get_sample(), plain.get_sample());
+        }
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut voice = Voice::new();
+        for op in &mut voice.operators {
+            op.env_gen.attack_rate = FP_ONE;
+        }
+        voice.routing = Routing::new();
+        voice.routing.output[3] = FP_ONE;
+        voice.note_on(FP::from(8.5));
+        for _ in 0..1000 {
+            voice.get_sample();
+        }
+
+        // a new depth fades in over the smoothing time, not at once
+        voice.routing.modulation[0][3] = FP_ONE;
+        voice.get_sample();
+        assert!(voice.operators[3].mod_input.repr.abs() <= FP_ONE.repr / SMOOTHING_SAMPLES as i32 + 1);
+
+        // a carrier taken out fades out
+        voice.routing.output[3] = FP_ZERO;
+        let peak = |voice : &mut Voice, samples : u32| (0..samples).map(|_| voice.get_sample().abs()).fold(0.0, f32::max);
+        assert!(peak(&mut voice, 48) > 0.5);
+        assert!(peak(&mut voice, SMOOTHING_SAMPLES) > 0.0);
+        assert_eq!(peak(&mut voice, 100), 0.0);
+    }
+
+    #[test]
+    fn test_feedback() {
+        let mut voice = Voice::new();
+        for op in &mut voice.operators {
+            op.env_gen.attack_rate = FP_ONE;
+        }
+        // op4 heard and feeding back into op1 at half depth
+        voice.routing = Routing::new();
+        voice.routing.output[3] = FP_ONE;
+        voice.routing.feedback[3][0] = FP::from(0.5);
+        voice.note_on(FP::from(8.5));
+
+        // op1 is modulated by the output op4 had one sample earlier
+        let mut last = FP_ZERO;
+        for _ in 0..500 {
+            let sample = FP::from(voice.get_sample());
+            assert_eq!(voice.operators[0].mod_input, last * FP::from(0.5));
+            assert_eq!(voice.operators[3].mod_input, FP_ZERO);
+            last = sample;
+        }
+        assert_ne!(last, FP_ZERO);
+
+        // a loop through the stack changes the sound; feedback into itself
+        // or a later operator has no effect
+        let mut stack = Voice::new();
+        for op in &mut stack.operators {
+            op.env_gen.attack_rate = FP_ONE;
+        }
+        stack.note_on(FP::from(8.5));
+        let (mut looped, mut ignored) = (stack, stack);
+        looped.routing.feedback[3][0] = FP_ONE;
+        ignored.routing.feedback[1][1] = FP_ONE;
+        ignored.routing.feedback[0][3] = FP_ONE;
+        let mut changed = false;
+        for _ in 0..2000 {
+            let sample = stack.get_sample();
+            changed |= looped.get_sample() != sample;
+            assert_eq!(ignored.get_sample(), sample);
+        }
+        assert!(changed);
+    }
+}
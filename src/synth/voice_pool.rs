@@ -0,0 +1,521 @@
+//! voice_pool
+//!
+//! plays keys on a set of voices sharing one patch: polyphonic with voice
+//! stealing, or monophonic with a note stack, optionally legato.
+//! The damper and sostenuto pedals hold notes past their key release.
+#[cfg(feature = "rodio")]
+use std::time::Duration;
+#[cfg(feature = "rodio")]
+use rodio::source::Source;
+
+use super::voice::*;
+use super::note_stack::*;
+use super::tuning::*;
+
+pub const POLYPHONY : usize = 16;
+
+const VELOCITY_MAX : f32 = 127.0;
+
+pub const CC_SUSTAIN : u8 = 64;
+pub const CC_SOSTENUTO : u8 = 66;
+pub const CC_ALL_NOTES_OFF : u8 = 123;
+const PEDAL_DOWN : u8 = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlayMode {
+    Poly,
+    /// one voice, every new key restarts the envelopes
+    Mono,
+    /// one voice, envelopes only restart when no key was held
+    Legato,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Slot<const N : usize> {
+    voice : FmVoice<N>,
+    /// the key this voice sounds, until it is released
+    key : Option<u8>,
+    /// the key is still pressed
+    down : bool,
+    /// held by the sostenuto pedal
+    latched : bool,
+    gain : f32,
+    age : u32,
+}
+
+impl<const N : usize> Slot<N> {
+    fn release(&mut self) {
+        self.key = None;
+        self.down = false;
+        self.latched = false;
+        self.voice.note_off();
+    }
+}
+
+/// voices of `N` operators, four unless given
+#[derive(Debug, Clone)]
+pub struct VoicePool<const N : usize = 4> {
+    pub tuning : Tuning,
+    pub priority : NotePriority,
+    mode : PlayMode,
+    sustain : bool,
+    sostenuto : bool,
+    slots : [Slot<N>; POLYPHONY],
+    stack : NoteStack,
+    clock : u32,
+}
+
+impl<const N : usize> VoicePool<N> {
+    pub fn new(patch : FmVoice<N>) -> VoicePool<N> {
+        VoicePool {
+            tuning : Tuning::default(),
+            priority : NotePriority::Last,
+            mode : PlayMode::Poly,
+            sustain : false,
+            sostenuto : false,
+            slots : [Slot {
+                voice : patch,
+                key : None,
+                down : false,
+                latched : false,
+                gain : 1.0,
+                age : 0
+            }; POLYPHONY],
+            stack : NoteStack::new(),
+            clock : 0,
+        }
+    }
+
+    /// give all voices `patch`; sounding notes are cut
+    pub fn set_patch(&mut self, patch : FmVoice<N>) {
+        self.stack.clear();
+        for slot in &mut self.slots {
+            slot.voice = patch;
+            slot.key = None;
+            slot.down = false;
+            slot.latched = false;
+        }
+    }
+
+    pub fn mode(&self) -> PlayMode {
+        self.mode
+    }
+
+    /// switching mode releases all sounding voices
+    pub fn set_mode(&mut self, mode : PlayMode) {
+        if mode != self.mode {
+            self.all_notes_off();
+            self.mode = mode;
+        }
+    }
+
+    pub fn voices(&self) -> impl Iterator<Item = &FmVoice<N>> {
+        self.slots.iter().map(|slot| &slot.voice)
+    }
+
+    /// to change the patch parameters of all voices
+    pub fn voices_mut(&mut self) -> impl Iterator<Item = &mut FmVoice<N>> {
+        self.slots.iter_mut().map(|slot| &mut slot.voice)
+    }
+
+    pub fn note_on(&mut self, key : u8, velocity : u8) {
+        let flog2 = match self.tuning.flog2(key) {
+            Some(flog2) => flog2,
+            None => return,
+        };
+        let gain = velocity as f32 / VELOCITY_MAX;
+
+        match self.mode {
+            PlayMode::Poly => {
+                let idx = self.allocate(key);
+                self.clock += 1;
+                let slot = &mut self.slots[idx];
+                // a stolen voice is no longer held by the sostenuto pedal
+                slot.latched &= slot.key == Some(key);
+                slot.key = Some(key);
+                slot.down = true;
+                slot.gain = gain;
+                slot.age = self.clock;
+                slot.voice.note_on(flog2);
+            }
+            PlayMode::Mono | PlayMode::Legato => {
+                let held = !self.stack.is_empty();
+                let previous = self.stack.top(self.priority);
+                self.stack.push(key);
+                if self.stack.top(self.priority) == previous {
+                    return;
+                }
+
+                let slot = &mut self.slots[0];
+                slot.latched &= slot.key == Some(key);
+                slot.key = Some(key);
+                slot.down = true;
+                if held && self.mode == PlayMode::Legato {
+                    slot.voice.set_freq(flog2);
+                } else {
+                    slot.gain = gain;
+                    slot.voice.note_on(flog2);
+                }
+            }
+        }
+    }
+
+    pub fn note_off(&mut self, key : u8) {
+        match self.mode {
+            PlayMode::Poly => {
+                for slot in &mut self.slots {
+                    if slot.key == Some(key) && slot.down {
+                        slot.down = false;
+                        if !self.sustain && !slot.latched {
+                            slot.release();
+                        }
+                    }
+                }
+            }
+            PlayMode::Mono | PlayMode::Legato => {
+                if !self.stack.contains(key) {
+                    return;
+                }
+                let previous = self.stack.top(self.priority);
+                self.stack.remove(key);
+
+                // fall back to the key that is still held
+                match self.stack.top(self.priority) {
+                    None => {
+                        let slot = &mut self.slots[0];
+                        slot.down = false;
+                        if !self.sustain && !slot.latched {
+                            slot.release();
+                        }
+                    }
+                    Some(top) if Some(top) != previous => {
+                        if let Some(flog2) = self.tuning.flog2(top) {
+                            let slot = &mut self.slots[0];
+                            slot.latched &= slot.key == Some(top);
+                            slot.key = Some(top);
+                            if self.mode == PlayMode::Legato {
+                                slot.voice.set_freq(flog2);
+                            } else {
+                                slot.voice.note_on(flog2);
+                            }
+                        }
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+    }
+
+    pub fn all_notes_off(&mut self) {
+        self.stack.clear();
+        for slot in &mut self.slots {
+            slot.release();
+        }
+    }
+
+    /// damper pedal: while down, released keys keep sounding
+    pub fn set_sustain(&mut self, down : bool) {
+        self.sustain = down;
+        if !down {
+            self.release_pending();
+        }
+    }
+
+    /// sostenuto pedal: holds only the keys that are down when it is pressed
+    pub fn set_sostenuto(&mut self, down : bool) {
+        if down && !self.sostenuto {
+            for slot in &mut self.slots {
+                slot.latched = slot.key.is_some() && slot.down;
+            }
+        }
+        if !down {
+            for slot in &mut self.slots {
+                slot.latched = false;
+            }
+            self.release_pending();
+        }
+        self.sostenuto = down;
+    }
+
+    pub fn sustain(&self) -> bool {
+        self.sustain
+    }
+
+    pub fn sostenuto(&self) -> bool {
+        self.sostenuto
+    }
+
+    /// handles the pedal and channel mode controllers, others are ignored
+    pub fn control_change(&mut self, cc : u8, value : u8) {
+        match cc {
+            CC_SUSTAIN       => self.set_sustain(value >= PEDAL_DOWN),
+            CC_SOSTENUTO     => self.set_sostenuto(value >= PEDAL_DOWN),
+            CC_ALL_NOTES_OFF => self.all_notes_off(),
+            _ => (),
+        }
+    }
+
+    /// release the voices whose key is up and no pedal holds them
+    fn release_pending(&mut self) {
+        for slot in &mut self.slots {
+            if slot.key.is_some() && !slot.down && !self.sustain && !slot.latched {
+                slot.release();
+            }
+        }
+    }
+
+    /// `value` is a centered MIDI pitch bend, -8192..8191
+    pub fn set_bend(&mut self, value : i32) {
+        for voice in self.voices_mut() {
+            voice.set_bend(value);
+        }
+    }
+
+    /// the key of the voice at `idx`, if it is held
+    pub fn key(&self, idx : usize) -> Option<u8> {
+        self.slots[idx].key
+    }
+
+    /// pick a slot for `key`: the one already playing it, a silent one,
+    /// the oldest released one, or else the oldest one
+    fn allocate(&self, key : u8) -> usize {
+        let slots = &self.slots;
+        if let Some(idx) = slots.iter().position(|slot| slot.key == Some(key)) {
+            return idx;
+        }
+        if let Some(idx) = slots.iter().position(|slot| slot.key.is_none() && !slot.voice.is_active()) {
+            return idx;
+        }
+        (0..POLYPHONY).filter(|idx| slots[*idx].key.is_none())
+            .min_by_key(|idx| slots[*idx].age)
+            .or_else(|| (0..POLYPHONY).min_by_key(|idx| slots[*idx].age))
+            .unwrap_or(0)
+    }
+
+    pub fn get_sample(&mut self) -> f32 {
+        let mut sample = 0.0;
+        for slot in &mut self.slots {
+            if slot.key.is_some() || slot.voice.is_active() {
+                sample += slot.voice.get_sample() * slot.gain;
+            }
+        }
+        sample
+    }
+}
+
+impl<const N : usize> Iterator for VoicePool<N> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        return Some(self.get_sample());
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl<const N : usize> Source for VoicePool<N> {
+    fn channels(&self) -> u16 {
+        return 1;
+    }
+
+     fn sample_rate(&self) -> u32 {
+        return super::SAMPLE_FREQ;
+     }
+
+     fn current_frame_len(&self) -> Option<usize> {
+        return None;
+     }
+
+     fn total_duration(&self) -> Option<Duration> {
+        return None;
+     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp::*;
+    use crate::synth::env_generator::EnvState;
+
+    fn patch() -> Voice {
+        let mut voice = Voice::new();
+        for op in &mut voice.operators {
+            op.env_gen.attack_rate = FP::from(0.5);
+            op.env_gen.decay_rate = FP::from(0.05);
+            op.env_gen.sustain_level = FP::from(0.5);
+        }
+        voice
+    }
+
+    fn run(pool : &mut VoicePool, samples : usize) {
+        for _ in 0..samples {
+            pool.get_sample();
+        }
+    }
+
+    fn state(pool : &VoicePool, idx : usize) -> EnvState {
+        pool.slots[idx].voice.operators[3].env_gen.state()
+    }
+
+    fn note(pool : &VoicePool, idx : usize) -> FP {
+        pool.slots[idx].voice.pitch.note()
+    }
+
+    #[test]
+    fn test_poly() {
+        let mut pool = VoicePool::new(patch());
+        pool.note_on(60, 100);
+        pool.note_on(64, 100);
+        assert_eq!((pool.key(0), pool.key(1)), (Some(60), Some(64)));
+
+        // steals the oldest voice when all are held
+        for key in 0..POLYPHONY as u8 {
+            pool.note_on(70 + key, 100);
+        }
+        assert_eq!((pool.key(0), pool.key(1)), (Some(84), Some(85)));
+
+        pool.note_off(85);
+        assert_eq!(pool.key(1), None);
+        assert_eq!(state(&pool, 1), EnvState::Release);
+    }
+
+    #[test]
+    fn test_legato() {
+        let mut pool = VoicePool::new(patch());
+        let tuning = pool.tuning;
+        pool.set_mode(PlayMode::Legato);
+
+        pool.note_on(60, 100);
+        run(&mut pool, 2000);
+        assert_eq!(state(&pool, 0), EnvState::Sustain);
+
+        // no retrigger, but pitch follows
+        pool.note_on(67, 100);
+        run(&mut pool, 1);
+        assert_eq!(state(&pool, 0), EnvState::Sustain);
+        assert_eq!(note(&pool, 0), tuning.flog2(67).unwrap());
+
+        // falls back to the held key
+        pool.note_off(67);
+        run(&mut pool, 1);
+        assert_eq!(state(&pool, 0), EnvState::Sustain);
+        assert_eq!(note(&pool, 0), tuning.flog2(60).unwrap());
+
+        pool.note_off(60);
+        assert_eq!(state(&pool, 0), EnvState::Release);
+
+        // mono retriggers
+        pool.set_mode(PlayMode::Mono);
+        pool.note_on(60, 100);
+        run(&mut pool, 2000);
+        pool.note_on(62, 100);
+        assert_eq!(state(&pool, 0), EnvState::Attack);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut pool = VoicePool::new(patch());
+        let tuning = pool.tuning;
+        pool.set_mode(PlayMode::Legato);
+        pool.priority = NotePriority::Low;
+
+        pool.note_on(60, 100);
+        pool.note_on(67, 100);
+        assert_eq!(pool.key(0), Some(60));
+        pool.note_on(55, 100);
+        assert_eq!(pool.key(0), Some(55));
+        pool.note_off(55);
+        assert_eq!(pool.key(0), Some(60));
+        run(&mut pool, 1);
+        assert_eq!(note(&pool, 0), tuning.flog2(60).unwrap());
+
+        pool.all_notes_off();
+        pool.priority = NotePriority::High;
+        pool.note_on(60, 100);
+        pool.note_on(55, 100);
+        assert_eq!(pool.key(0), Some(60));
+        pool.note_on(72, 100);
+        pool.note_off(72);
+        assert_eq!(pool.key(0), Some(60));
+    }
+
+    #[test]
+    fn test_sustain() {
+        let mut pool = VoicePool::new(patch());
+        pool.note_on(60, 100);
+        pool.control_change(CC_SUSTAIN, 127);
+        pool.note_off(60);
+        pool.note_on(64, 100);
+        pool.note_off(64);
+        assert_eq!((pool.key(0), pool.key(1)), (Some(60), Some(64)));
+        assert_eq!(state(&pool, 0), EnvState::Attack);
+
+        // the same key again retriggers its own voice
+        pool.note_on(60, 100);
+        assert_eq!(pool.key(2), None);
+
+        pool.control_change(CC_SUSTAIN, 0);
+        assert_eq!((pool.key(0), pool.key(1)), (Some(60), None));
+        assert_eq!(state(&pool, 1), EnvState::Release);
+        pool.note_off(60);
+        assert_eq!(pool.key(0), None);
+    }
+
+    #[test]
+    fn test_sostenuto() {
+        let mut pool = VoicePool::new(patch());
+        pool.note_on(60, 100);
+        pool.note_on(64, 100);
+        pool.note_off(64);
+        pool.control_change(CC_SOSTENUTO, 127);
+
+        // only 60 was down when the pedal went down
+        pool.note_on(67, 100);
+        pool.note_off(60);
+        pool.note_off(67);
+        assert_eq!(pool.key(0), Some(60));
+        assert_eq!(pool.key(2), None);
+
+        pool.control_change(CC_SOSTENUTO, 0);
+        assert_eq!(pool.key(0), None);
+
+        // with both pedals, lifting one keeps the notes held by the other
+        pool.note_on(60, 100);
+        pool.control_change(CC_SOSTENUTO, 127);
+        pool.control_change(CC_SUSTAIN, 127);
+        pool.note_on(62, 100);
+        pool.note_off(60);
+        pool.note_off(62);
+        pool.control_change(CC_SUSTAIN, 0);
+        assert_eq!((0..POLYPHONY).filter(|idx| pool.key(*idx).is_some()).count(), 1);
+        pool.control_change(CC_SOSTENUTO, 0);
+        assert_eq!((0..POLYPHONY).filter(|idx| pool.key(*idx).is_some()).count(), 0);
+    }
+
+    #[test]
+    fn test_sostenuto_steal() {
+        // a full pool held by the pedal: a new key steals a latched voice,
+        // which is then released with its key
+        let mut pool = VoicePool::new(patch());
+        for key in 0..POLYPHONY as u8 {
+            pool.note_on(60 + key, 100);
+        }
+        pool.control_change(CC_SOSTENUTO, 127);
+        for key in 0..POLYPHONY as u8 {
+            pool.note_off(60 + key);
+        }
+        pool.note_on(100, 100);
+        pool.note_off(100);
+        assert_eq!((0..POLYPHONY).filter(|idx| pool.key(*idx).is_some()).count(), POLYPHONY - 1);
+        assert!((0..POLYPHONY).all(|idx| pool.key(idx) != Some(100)));
+
+        // the same for the one voice in mono mode
+        let mut pool = VoicePool::new(patch());
+        pool.set_mode(PlayMode::Mono);
+        pool.note_on(60, 100);
+        pool.control_change(CC_SOSTENUTO, 127);
+        pool.note_on(62, 100);
+        pool.note_off(60);
+        pool.note_off(62);
+        assert_eq!(pool.key(0), None);
+    }
+}
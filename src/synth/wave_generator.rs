@@ -4,7 +4,7 @@
 
 use crate::fp::*;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum WaveForm {
 	FullSine,
 	HalfSine,
@@ -16,6 +16,18 @@ pub enum WaveForm {
 	Square
 }
 
+/// all waveforms, in the order of their index
+pub const WAVEFORMS : [WaveForm; 8] = [
+	WaveForm::FullSine,
+	WaveForm::HalfSine,
+	WaveForm::DblHalfSine,
+	WaveForm::DblQuartSine,
+	WaveForm::FastSine,
+	WaveForm::FastHalfSine,
+	WaveForm::Sawish,
+	WaveForm::Square
+];
+
 #[derive(Debug, Copy, Clone)]
 pub struct WaveGenerator {
 	pub waveform : WaveForm
@@ -29,6 +41,12 @@ const FP_EIGHTH : FP = FP { repr: 0x0_1FFF };
 const Q_SHIFT : i32 = 14;
 const Q_MASK  : u16 = 0x03;
 
+impl Default for WaveGenerator {
+	fn default() -> WaveGenerator {
+		WaveGenerator::new()
+	}
+}
+
 impl WaveGenerator {
 	pub fn new() -> WaveGenerator {
 		WaveGenerator {